@@ -0,0 +1,274 @@
+use crate::board::{PieceColor, Point, BOARD_START};
+
+/*
+    A set of squares packed into a u64, bit 0 is a8 and bit 63 is h1
+
+    This is the same order the squares of a Move are stored in, so moving
+    one row down the board is the same as shifting left by 8
+*/
+pub type Bitboard = u64;
+
+pub const EMPTY: Bitboard = 0;
+pub const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+pub const FILE_H: Bitboard = FILE_A << 7;
+pub const RANK_8: Bitboard = 0xff;
+pub const RANK_1: Bitboard = RANK_8 << 56;
+pub const LIGHT_SQUARES: Bitboard = 0xaa55_aa55_aa55_aa55; // a8 is a light square
+pub const DARK_SQUARES: Bitboard = !LIGHT_SQUARES;
+
+// Convert a point on the 12x12 board into a square index from 0 (a8) to 63 (h1)
+pub const fn square(point: Point) -> usize {
+    (point.0 - BOARD_START) * 8 + (point.1 - BOARD_START)
+}
+
+// Convert a square index from 0 (a8) to 63 (h1) back into a point on the 12x12 board
+pub const fn point(square: usize) -> Point {
+    Point(square / 8 + BOARD_START, square % 8 + BOARD_START)
+}
+
+// Get the bitboard with only the given point set
+pub const fn bit(point: Point) -> Bitboard {
+    1 << square(point)
+}
+
+/*
+    Iterate over the squares in a bitboard, from a8 to h1
+*/
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == EMPTY {
+            return None;
+        }
+        let square = self.0.trailing_zeros() as usize;
+        // clear the lowest set bit
+        self.0 &= self.0 - 1;
+        Some(square)
+    }
+}
+
+pub fn squares(bitboard: Bitboard) -> Squares {
+    Squares(bitboard)
+}
+
+/*
+    Build a table of the squares a piece can reach in one step from every square,
+    steps that would leave the board are dropped
+*/
+const fn step_attacks(steps: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [EMPTY; 64];
+    let mut square = 0;
+    while square < 64 {
+        let row = (square / 8) as i8;
+        let col = (square % 8) as i8;
+        let mut i = 0;
+        while i < steps.len() {
+            let (r, c) = (row + steps[i].0, col + steps[i].1);
+            if r >= 0 && r < 8 && c >= 0 && c < 8 {
+                table[square] |= 1 << (r * 8 + c);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+pub const KNIGHT_ATTACKS: [Bitboard; 64] = step_attacks(&[
+    (1, 2),
+    (1, -2),
+    (2, 1),
+    (2, -1),
+    (-1, 2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+]);
+
+pub const KING_ATTACKS: [Bitboard; 64] = step_attacks(&[
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, 1),
+    (0, -1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+]);
+
+// white pawns move up the board towards row 0, black pawns move down
+const WHITE_PAWN_ATTACKS: [Bitboard; 64] = step_attacks(&[(-1, -1), (-1, 1)]);
+const BLACK_PAWN_ATTACKS: [Bitboard; 64] = step_attacks(&[(1, -1), (1, 1)]);
+
+// The squares a pawn of the given color attacks from a square
+pub fn pawn_attacks(color: PieceColor, square: usize) -> Bitboard {
+    match color {
+        PieceColor::White => WHITE_PAWN_ATTACKS[square],
+        PieceColor::Black => BLACK_PAWN_ATTACKS[square],
+    }
+}
+
+/*
+    Sliding attacks use the classical approach, see https://www.chessprogramming.org/Classical_Approach
+
+    Every ray from every square is precomputed, the nearest piece on a ray is found with a bit scan
+    and everything past it is cut off by removing the same ray as seen from the blocking piece
+*/
+// (row step, col step) for each ray, the first four head towards higher square indexes
+const RAY_DIRECTIONS: [(i8, i8); 8] = [
+    (1, 0),
+    (0, 1),
+    (1, 1),
+    (1, -1),
+    (-1, 0),
+    (0, -1),
+    (-1, -1),
+    (-1, 1),
+];
+const ROOK_RAYS: [usize; 4] = [0, 1, 4, 5];
+const BISHOP_RAYS: [usize; 4] = [2, 3, 6, 7];
+
+const fn ray_table() -> [[Bitboard; 64]; 8] {
+    let mut table = [[EMPTY; 64]; 8];
+    let mut direction = 0;
+    while direction < 8 {
+        let (r, c) = RAY_DIRECTIONS[direction];
+        let mut square = 0;
+        while square < 64 {
+            let mut row = (square / 8) as i8 + r;
+            let mut col = (square % 8) as i8 + c;
+            while row >= 0 && row < 8 && col >= 0 && col < 8 {
+                table[direction][square] |= 1 << (row * 8 + col);
+                row += r;
+                col += c;
+            }
+            square += 1;
+        }
+        direction += 1;
+    }
+    table
+}
+
+const RAYS: [[Bitboard; 64]; 8] = ray_table();
+
+fn ray_attacks(square: usize, occupied: Bitboard, directions: &[usize; 4]) -> Bitboard {
+    let mut attacks = EMPTY;
+    for &direction in directions {
+        let ray = RAYS[direction][square];
+        let blockers = ray & occupied;
+        if blockers == EMPTY {
+            attacks |= ray;
+            continue;
+        }
+        let blocker = if direction < 4 {
+            blockers.trailing_zeros()
+        } else {
+            63 - blockers.leading_zeros()
+        };
+        attacks |= ray ^ RAYS[direction][blocker as usize];
+    }
+    attacks
+}
+
+// The squares a rook on this square attacks given the occupied squares of the board
+pub fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(square, occupied, &ROOK_RAYS)
+}
+
+// The squares a bishop on this square attacks given the occupied squares of the board
+pub fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(square, occupied, &BISHOP_RAYS)
+}
+
+pub fn queen_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::rand_core::{RngCore, SeedableRng};
+
+    #[test]
+    fn square_conversions() {
+        assert_eq!(square(Point(2, 2)), 0);
+        assert_eq!(square(Point(9, 9)), 63);
+        for i in 0..64 {
+            assert_eq!(square(point(i)), i);
+        }
+        assert_eq!(bit(Point(9, 2)), FILE_A & RANK_1);
+        assert_eq!(bit(Point(2, 9)), FILE_H & RANK_8);
+        for i in 0..64 {
+            let Point(row, col) = point(i);
+            let light = (row + col) % 2 == 0;
+            assert_eq!(bit(point(i)) & LIGHT_SQUARES != 0, light);
+            assert_eq!(bit(point(i)) & DARK_SQUARES != 0, !light);
+        }
+    }
+
+    #[test]
+    fn squares_iterated_in_order() {
+        let found: Vec<usize> = squares(bit(Point(9, 9)) | bit(Point(2, 3)) | 1).collect();
+        assert_eq!(found, vec![0, 1, 63]);
+        assert_eq!(squares(EMPTY).count(), 0);
+    }
+
+    #[test]
+    fn step_attacks_stay_on_board() {
+        assert_eq!(KNIGHT_ATTACKS[0].count_ones(), 2);
+        assert_eq!(KNIGHT_ATTACKS[square(Point(5, 5))].count_ones(), 8);
+        assert_eq!(KING_ATTACKS[63].count_ones(), 3);
+        assert_eq!(
+            pawn_attacks(PieceColor::White, square(Point(8, 2))),
+            bit(Point(7, 3))
+        );
+        assert_eq!(
+            pawn_attacks(PieceColor::Black, square(Point(3, 9))),
+            bit(Point(4, 8))
+        );
+    }
+
+    // walk out from the square one step at a time until a piece or the edge of the board is hit
+    fn walk_rays(square: usize, occupied: Bitboard, directions: &[usize; 4]) -> Bitboard {
+        let mut attacks = EMPTY;
+        for &direction in directions {
+            let (r, c) = RAY_DIRECTIONS[direction];
+            let mut row = (square / 8) as i8 + r;
+            let mut col = (square % 8) as i8 + c;
+            while (0..8).contains(&row) && (0..8).contains(&col) {
+                let target = 1 << (row * 8 + col);
+                attacks |= target;
+                if occupied & target != EMPTY {
+                    break;
+                }
+                row += r;
+                col += c;
+            }
+        }
+        attacks
+    }
+
+    #[test]
+    fn sliding_attacks_match_walked_rays() {
+        // a handful of pseudo random occupancies for every square
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+        for square in 0..64 {
+            for _ in 0..32 {
+                let occupied = rng.next_u64() & rng.next_u64();
+                assert_eq!(
+                    rook_attacks(square, occupied),
+                    walk_rays(square, occupied, &ROOK_RAYS)
+                );
+                assert_eq!(
+                    bishop_attacks(square, occupied),
+                    walk_rays(square, occupied, &BISHOP_RAYS)
+                );
+            }
+        }
+        assert_eq!(rook_attacks(0, EMPTY), (FILE_A | RANK_8) & !1);
+        assert_eq!(queen_attacks(square(Point(5, 5)), EMPTY).count_ones(), 27);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::bitboard::{self, Bitboard};
 use crate::engine::*;
 use crate::utils::*;
 use crate::zobrist::ZobristHasher;
@@ -172,6 +173,14 @@ impl PieceColor {
             White => Black,
         }
     }
+
+    // get an index for a color, helpful for arrays
+    pub fn index(self) -> usize {
+        match self {
+            White => 0,
+            Black => 1,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -239,10 +248,10 @@ impl FromStr for Point {
             _ => return Err("Invalid column"),
         };
 
-        let row = BOARD_END - (r.to_digit(10).unwrap() as usize);
-        if !(BOARD_START..BOARD_END).contains(&row) {
-            return Err("Invalid row");
-        }
+        let row = match r.to_digit(10) {
+            Some(rank @ 1..=8) => BOARD_END - rank as usize,
+            _ => return Err("Invalid row"),
+        };
 
         Ok(Point(row, col + BOARD_START))
     }
@@ -279,9 +288,135 @@ impl fmt::Display for Point {
     }
 }
 
+/*
+    A compact representation of a move, the squares are stored as an index
+    from 0 (a8) to 63 (h1) and the flags describe any special handling the
+    move requires when it is made on the board
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Move {
+    from: u8,
+    to: u8,
+    pub promotion: Option<PieceKind>,
+    pub flags: u8,
+}
+
+impl Move {
+    pub const QUIET: u8 = 0;
+    pub const CAPTURE: u8 = 1;
+    pub const EN_PASSANT: u8 = 1 << 1; // always set together with CAPTURE
+    pub const CASTLE: u8 = 1 << 2; // from and to are the squares of the king
+    pub const DOUBLE_PAWN_PUSH: u8 = 1 << 3;
+
+    pub const fn new(from: Point, to: Point, promotion: Option<PieceKind>, flags: u8) -> Move {
+        Move {
+            from: Self::index(from),
+            to: Self::index(to),
+            promotion,
+            flags,
+        }
+    }
+
+    const fn index(point: Point) -> u8 {
+        bitboard::square(point) as u8
+    }
+
+    pub fn from(self) -> Point {
+        bitboard::point(self.from as usize)
+    }
+
+    pub fn to(self) -> Point {
+        bitboard::point(self.to as usize)
+    }
+
+    // The squares as bitboard indexes, handy for tables indexed by square
+    pub fn from_square(self) -> usize {
+        self.from as usize
+    }
+
+    pub fn to_square(self) -> usize {
+        self.to as usize
+    }
+
+    pub fn is_capture(self) -> bool {
+        self.flags & Move::CAPTURE != 0
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flags & Move::EN_PASSANT != 0
+    }
+
+    pub fn is_castle(self) -> bool {
+        self.flags & Move::CASTLE != 0
+    }
+
+    pub fn is_double_pawn_push(self) -> bool {
+        self.flags & Move::DOUBLE_PAWN_PUSH != 0
+    }
+
+    // A quiet move does not change the material on the board
+    pub fn is_quiet(self) -> bool {
+        !self.is_capture() && self.promotion.is_none()
+    }
+
+    /*
+        Pack the move into the low 19 bits of an integer, 6 bits for each square,
+        3 for the promotion and 4 for the flags
+    */
+    pub fn to_bits(self) -> u32 {
+        let promotion = self.promotion.map_or(0, |kind| kind.index() as u32 + 1);
+        self.from as u32 | (self.to as u32) << 6 | promotion << 12 | (self.flags as u32) << 15
+    }
+
+    // Unpack a move packed by to_bits
+    pub fn from_bits(bits: u32) -> Move {
+        let promotion = match (bits >> 12) & 7 {
+            1 => Some(King),
+            2 => Some(Queen),
+            3 => Some(Rook),
+            4 => Some(Bishop),
+            5 => Some(Knight),
+            6 => Some(Pawn),
+            _ => None,
+        };
+        Move {
+            from: (bits & 63) as u8,
+            to: ((bits >> 6) & 63) as u8,
+            promotion,
+            flags: ((bits >> 15) & 15) as u8,
+        }
+    }
+}
+
+impl fmt::Display for Move {
+    // Write the move in long algebraic notation (ex: e2e4, e7e8q)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.from(), self.to())?;
+        if let Some(kind) = self.promotion {
+            write!(f, "{}", kind.alg())?;
+        }
+        Ok(())
+    }
+}
+
+/*
+    Everything needed to take back a move that can not be worked out from the move itself
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct MoveUndo {
+    captured: Square,
+    pawn_double_move: Option<Point>,
+    castling_rights: [bool; 4],
+    half_move_clock: u16,
+    full_move_number: u32,
+    zobrist_key: u64,
+}
+
 #[derive(Clone)]
 pub struct BoardState {
     pub board: [[Square; 12]; 12],
+    pub pieces: [[Bitboard; 6]; 2], // the squares holding each kind of piece, indexed by [color][kind]
+    pub occupied: [Bitboard; 2],    // the squares holding any piece of a color, indexed by [color]
     pub to_move: PieceColor,
     pub pawn_double_move: Option<Point>, // if a pawn, on the last move, made a double move, this is set, otherwise this is None
     pub white_king_location: Point,
@@ -290,16 +425,15 @@ pub struct BoardState {
     pub white_queen_side_castle: bool,
     pub black_king_side_castle: bool,
     pub black_queen_side_castle: bool,
-    pub order_heuristic: i32, // value set to help order this board, a higher value means this board state will be considered first
-    pub last_move: Option<(Point, Point)>, // the start and last position of the last move made
-    pub pawn_promotion: Option<Piece>, // set to the chosen pawn promotion type
     pub zobrist_key: u64,
+    pub half_move_clock: u16, // plies since the last capture or pawn move, used for the fifty move rule
+    pub full_move_number: u32, // starts at 1 and is incremented after black moves
 }
 
 impl BoardState {
     // Parse the standard fen string notation (en.wikipedia.org/wiki/Forsyth–Edwards_Notation) and return a board state
-    pub fn from_fen(fen: &str) -> Result<BoardState, &str> {
-        let mut board = [[Square::Boundary; 12]; 12];
+    pub fn from_fen(fen: &str) -> Result<BoardState, &'static str> {
+        let mut squares = [[Square::Boundary; 12]; 12];
         let mut fen = fen.to_string();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
         let mut zobrist_key = 0;
@@ -322,15 +456,15 @@ impl BoardState {
         let castling_privileges = fen_config[2];
         let en_passant = fen_config[3];
 
-        let half_move_clock = fen_config[4].parse::<u8>();
-        if half_move_clock.is_err() {
-            return Err("Could not parse fen string: Invalid half move value");
-        }
+        let half_move_clock = match fen_config[4].parse::<u16>() {
+            Ok(half_move_clock) => half_move_clock,
+            Err(_) => return Err("Could not parse fen string: Invalid half move value"),
+        };
 
-        let full_move_clock = fen_config[5].parse::<u8>();
-        if full_move_clock.is_err() {
-            return Err("Could not parse fen string: Invalid full move value");
-        }
+        let full_move_number = match fen_config[5].parse::<u32>() {
+            Ok(full_move_number) => full_move_number,
+            Err(_) => return Err("Could not parse fen string: Invalid full move value"),
+        };
 
         let fen_rows: Vec<&str> = fen_config[0].split('/').collect();
 
@@ -338,32 +472,31 @@ impl BoardState {
             return Err("Could not parse fen string: Invalid number of rows provided, 8 expected");
         }
 
-        let mut row: usize = BOARD_START;
         let mut col: usize = BOARD_START;
         let mut white_king_location = Point(0, 0);
         let mut black_king_location = Point(0, 0);
-        for fen_row in fen_rows {
+        for (row, fen_row) in (BOARD_START..).zip(fen_rows) {
             for square in fen_row.chars() {
                 if row >= BOARD_END || col >= BOARD_END {
                     return Err("Too many squares specified for board");
                 }
 
-                if square.is_digit(10) {
+                if square.is_ascii_digit() {
                     let square_skip_count = square.to_digit(10).unwrap() as usize;
                     if square_skip_count + col > BOARD_END {
                         return Err("Could not parse fen string: Index out of bounds");
                     }
                     for _ in 0..square_skip_count {
-                        board[row][col] = Square::Empty;
+                        squares[row][col] = Square::Empty;
                         col += 1;
                     }
                 } else {
-                    board[row][col] = match Self::piece_from_fen_string_char(square) {
+                    squares[row][col] = match Self::piece_from_fen_string_char(square) {
                         Some(piece) => Square::Full(piece),
                         None => return Err("Could not parse fen string: Invalid character found"),
                     };
 
-                    if let Square::Full(Piece { kind, color }) = board[row][col] {
+                    if let Square::Full(Piece { kind, color }) = squares[row][col] {
                         zobrist_key ^= zobrist_hasher
                             .get_val_for_piece(Piece { kind, color }, Point(row, col));
                         if kind == King {
@@ -379,7 +512,6 @@ impl BoardState {
             if col != BOARD_END {
                 return Err("Could not parse fen string: Complete row was not specified");
             }
-            row += 1;
             col = BOARD_START;
         }
 
@@ -397,20 +529,29 @@ impl BoardState {
         }
 
         let mut board = BoardState {
-            board,
+            board: [[Square::Boundary; 12]; 12],
+            pieces: [[bitboard::EMPTY; 6]; 2],
+            occupied: [bitboard::EMPTY; 2],
             to_move,
             white_king_location,
             black_king_location,
             pawn_double_move: en_passant_pos,
-            white_king_side_castle: castling_privileges.find('K') != None,
-            white_queen_side_castle: castling_privileges.find('Q') != None,
-            black_king_side_castle: castling_privileges.find('k') != None,
-            black_queen_side_castle: castling_privileges.find('q') != None,
-            order_heuristic: 0,
-            last_move: None,
-            pawn_promotion: None,
+            white_king_side_castle: castling_privileges.contains('K'),
+            white_queen_side_castle: castling_privileges.contains('Q'),
+            black_king_side_castle: castling_privileges.contains('k'),
+            black_queen_side_castle: castling_privileges.contains('q'),
             zobrist_key,
+            half_move_clock,
+            full_move_number,
         };
+        // fill in the bitboards from the parsed squares
+        for (i, row) in squares.iter().enumerate() {
+            for (j, &square) in row.iter().enumerate() {
+                if square != Square::Boundary {
+                    board.set_square(Point(i, j), square);
+                }
+            }
+        }
 
         if board.white_king_side_castle {
             board.zobrist_key ^= zobrist_hasher.get_val_for_castling(CastlingType::WhiteKingSide);
@@ -428,6 +569,67 @@ impl BoardState {
         Ok(board)
     }
 
+    // Write out this board state in fen notation, the inverse of from_fen
+    pub fn to_fen(&self) -> String {
+        let mut rows: Vec<String> = Vec::with_capacity(8);
+        for i in BOARD_START..BOARD_END {
+            let mut row = String::new();
+            let mut empty_squares = 0;
+            for j in BOARD_START..BOARD_END {
+                if let Square::Full(piece) = self.board[i][j] {
+                    if empty_squares > 0 {
+                        row.push_str(&empty_squares.to_string());
+                        empty_squares = 0;
+                    }
+                    row.push_str(piece.simple_char());
+                } else {
+                    empty_squares += 1;
+                }
+            }
+            if empty_squares > 0 {
+                row.push_str(&empty_squares.to_string());
+            }
+            rows.push(row);
+        }
+
+        let to_move = match self.to_move {
+            White => "w",
+            Black => "b",
+        };
+
+        let mut castling_privileges = String::new();
+        if self.white_king_side_castle {
+            castling_privileges.push('K');
+        }
+        if self.white_queen_side_castle {
+            castling_privileges.push('Q');
+        }
+        if self.black_king_side_castle {
+            castling_privileges.push('k');
+        }
+        if self.black_queen_side_castle {
+            castling_privileges.push('q');
+        }
+        if castling_privileges.is_empty() {
+            castling_privileges.push('-');
+        }
+
+        let en_passant = match self.pawn_double_move {
+            Some(point) => point.to_string(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            rows.join("/"),
+            to_move,
+            castling_privileges,
+            en_passant,
+            self.half_move_clock,
+            self.full_move_number
+        )
+    }
+
     fn piece_from_fen_string_char(piece: char) -> Option<Piece> {
         match piece {
             'r' => Some(Piece {
@@ -482,6 +684,28 @@ impl BoardState {
         }
     }
 
+    /*
+        Check if neither player has enough material left to deliver checkmate, which makes the game a dead draw
+
+        This covers K v K, KB v K, KN v K and endings where every bishop left is on the same colored squares
+    */
+    pub fn is_insufficient_material(&self) -> bool {
+        let [white, black] = self.pieces;
+        let both_sides = |kind: PieceKind| white[kind.index()] | black[kind.index()];
+
+        // any pawn, rook or queen can still force a win
+        if both_sides(Pawn) | both_sides(Rook) | both_sides(Queen) != 0 {
+            return false;
+        }
+
+        let bishops = both_sides(Bishop);
+        match both_sides(Knight).count_ones() {
+            0 => bishops & bitboard::LIGHT_SQUARES == 0 || bishops & bitboard::DARK_SQUARES == 0,
+            1 => bishops == 0,
+            _ => false,
+        }
+    }
+
     pub fn pretty_print_board(&self) {
         println!("a b c d e f g h");
         for i in BOARD_START..BOARD_END {
@@ -530,6 +754,188 @@ impl BoardState {
         self.zobrist_key ^= zobrist_hasher.get_black_to_move_val();
     }
 
+    /*
+        Play a move on the board, the move is assumed to be at least pseudo-legal
+        Returns the information needed to take the move back with unmake_move
+    */
+    pub fn make_move(&mut self, mov: Move, zobrist_hasher: &ZobristHasher) -> MoveUndo {
+        let (start, end) = (mov.from(), mov.to());
+        let undo = self.move_undo(self.board[end.0][end.1]);
+
+        let piece = match self.board[start.0][start.1] {
+            Square::Full(piece) => piece,
+            _ => panic!("Trying to move a piece that does not exist"),
+        };
+
+        self.update_move_clocks(piece.kind == Pawn || mov.is_capture());
+        self.unset_pawn_double_move(zobrist_hasher);
+
+        if mov.is_en_passant() {
+            // the captured pawn is beside the moving pawn, not on the target square
+            let captured = Point(start.0, end.1);
+            self.set_square(captured, Square::Empty);
+            self.zobrist_key ^=
+                zobrist_hasher.get_val_for_piece(Piece::pawn(piece.color.opposite()), captured);
+        }
+
+        self.move_piece(start, end, zobrist_hasher);
+
+        if let Some(kind) = mov.promotion {
+            let promotion_piece = Piece {
+                color: piece.color,
+                kind,
+            };
+            self.set_square(end, Square::Full(promotion_piece));
+            self.zobrist_key ^= zobrist_hasher.get_val_for_piece(piece, end)
+                ^ zobrist_hasher.get_val_for_piece(promotion_piece, end);
+        }
+
+        if mov.is_castle() {
+            let (rook_start, rook_end) = castling_rook_squares(start, end);
+            self.move_piece(rook_start, rook_end, zobrist_hasher);
+        }
+
+        // if the king moves, take away castling privileges
+        if piece.kind == King {
+            match piece.color {
+                White => {
+                    self.white_king_location = end;
+                    self.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
+                    self.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
+                }
+                Black => {
+                    self.black_king_location = end;
+                    self.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
+                    self.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
+                }
+            }
+        }
+
+        // if a rook moves or is captured, take away castling privileges
+        for point in [start, end] {
+            if point == Point(BOARD_END - 1, BOARD_END - 1) {
+                self.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
+            } else if point == Point(BOARD_END - 1, BOARD_START) {
+                self.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
+            } else if point == Point(BOARD_START, BOARD_START) {
+                self.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
+            } else if point == Point(BOARD_START, BOARD_END - 1) {
+                self.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
+            }
+        }
+
+        // record the space *behind* the pawn, ie the valid en passant capture square
+        if mov.is_double_pawn_push() {
+            let en_passant_square = Point((start.0 + end.0) / 2, start.1);
+            self.pawn_double_move = Some(en_passant_square);
+            self.zobrist_key ^= zobrist_hasher.get_val_for_en_passant(en_passant_square.1);
+        }
+
+        self.swap_color(zobrist_hasher);
+        undo
+    }
+
+    /*
+        Pass the turn to the opponent without moving a piece, used for null move pruning
+        The en passant capture is no longer available, just as after any other move, but
+        the move clocks are left alone since no move was played
+
+        Returns the information needed to take it back with unmake_null_move
+    */
+    pub fn make_null_move(&mut self, zobrist_hasher: &ZobristHasher) -> MoveUndo {
+        let undo = self.move_undo(Square::Empty);
+        self.unset_pawn_double_move(zobrist_hasher);
+        self.swap_color(zobrist_hasher);
+        undo
+    }
+
+    // Take back a move made with make_null_move
+    pub fn unmake_null_move(&mut self, undo: &MoveUndo) {
+        self.to_move = self.to_move.opposite();
+        self.pawn_double_move = undo.pawn_double_move;
+        self.zobrist_key = undo.zobrist_key;
+    }
+
+    fn move_undo(&self, captured: Square) -> MoveUndo {
+        MoveUndo {
+            captured,
+            pawn_double_move: self.pawn_double_move,
+            castling_rights: [
+                self.white_king_side_castle,
+                self.white_queen_side_castle,
+                self.black_king_side_castle,
+                self.black_queen_side_castle,
+            ],
+            half_move_clock: self.half_move_clock,
+            full_move_number: self.full_move_number,
+            zobrist_key: self.zobrist_key,
+        }
+    }
+
+    /*
+        Take back a move made with make_move, this must be the most recent move
+        made on this board
+    */
+    pub fn unmake_move(&mut self, mov: Move, undo: &MoveUndo) {
+        let (start, end) = (mov.from(), mov.to());
+        self.to_move = self.to_move.opposite();
+        let color = self.to_move;
+
+        let piece = match mov.promotion {
+            Some(_) => Piece::pawn(color),
+            None => match self.board[end.0][end.1] {
+                Square::Full(piece) => piece,
+                _ => panic!("Trying to take back a move that was not made"),
+            },
+        };
+        self.set_square(start, Square::Full(piece));
+        self.set_square(end, undo.captured);
+
+        if mov.is_en_passant() {
+            self.set_square(
+                Point(start.0, end.1),
+                Square::Full(Piece::pawn(color.opposite())),
+            );
+        }
+
+        if mov.is_castle() {
+            let (rook_start, rook_end) = castling_rook_squares(start, end);
+            self.set_square(rook_start, Square::Full(Piece::rook(color)));
+            self.set_square(rook_end, Square::Empty);
+        }
+
+        if piece.kind == King {
+            match color {
+                White => self.white_king_location = start,
+                Black => self.black_king_location = start,
+            }
+        }
+
+        self.pawn_double_move = undo.pawn_double_move;
+        self.white_king_side_castle = undo.castling_rights[0];
+        self.white_queen_side_castle = undo.castling_rights[1];
+        self.black_king_side_castle = undo.castling_rights[2];
+        self.black_queen_side_castle = undo.castling_rights[3];
+        self.half_move_clock = undo.half_move_clock;
+        self.full_move_number = undo.full_move_number;
+        self.zobrist_key = undo.zobrist_key;
+    }
+
+    /*
+        Helper function to advance the move counters once the player to move has made their move,
+        the half move clock starts over after any capture or pawn move
+    */
+    pub fn update_move_clocks(&mut self, reset_half_move_clock: bool) {
+        if reset_half_move_clock {
+            self.half_move_clock = 0;
+        } else {
+            self.half_move_clock += 1;
+        }
+        if self.to_move == Black {
+            self.full_move_number += 1;
+        }
+    }
+
     /*
         Helper function to take away castling rights, updates the zobrist as well if required
 
@@ -567,23 +973,62 @@ impl BoardState {
         }
     }
 
+    /*
+        Helper function to change what is on a square, keeps the bitboards in sync with the
+        board but does not touch the zobrist key
+    */
+    fn set_square(&mut self, point: Point, square: Square) {
+        let bit = bitboard::bit(point);
+        if let Square::Full(piece) = self.board[point.0][point.1] {
+            self.pieces[piece.color.index()][piece.index()] &= !bit;
+            self.occupied[piece.color.index()] &= !bit;
+        }
+        if let Square::Full(piece) = square {
+            self.pieces[piece.color.index()][piece.index()] |= bit;
+            self.occupied[piece.color.index()] |= bit;
+        }
+        self.board[point.0][point.1] = square;
+    }
+
+    // Every square with a piece on it
+    pub fn all_occupied(&self) -> Bitboard {
+        self.occupied[0] | self.occupied[1]
+    }
+
     /*
         Helper function to move a piece on the board, will also update the zobrist
         hash of the board correctly even with a capture
     */
     pub fn move_piece(&mut self, start: Point, end: Point, zobrist_hasher: &ZobristHasher) {
         if let Square::Full(cur_piece) = self.board[start.0][start.1] {
-            self.board[start.0][start.1] = Square::Empty;
+            self.set_square(start, Square::Empty);
             if let Square::Full(target_piece) = self.board[end.0][end.1] {
                 self.zobrist_key ^= zobrist_hasher.get_val_for_piece(target_piece, end);
             }
-            self.board[end.0][end.1] = Square::Full(cur_piece);
+            self.set_square(end, Square::Full(cur_piece));
             self.zobrist_key ^= zobrist_hasher.get_val_for_piece(cur_piece, start)
                 ^ zobrist_hasher.get_val_for_piece(cur_piece, end);
         }
     }
 }
 
+/*
+    Given the start and end square of the king when castling, get the start and end square of the rook
+*/
+fn castling_rook_squares(king_start: Point, king_end: Point) -> (Point, Point) {
+    if king_end.1 > king_start.1 {
+        (
+            Point(king_start.0, BOARD_END - 1),
+            Point(king_start.0, king_end.1 - 1),
+        )
+    } else {
+        (
+            Point(king_start.0, BOARD_START),
+            Point(king_start.0, king_end.1 + 1),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -654,6 +1099,29 @@ mod tests {
 
     // Zobrist hashing tests
 
+    #[test]
+    fn null_move_clears_en_passant() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut b =
+            BoardState::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2")
+                .unwrap();
+        let undo = b.make_null_move(&zobrist_hasher);
+        let expected =
+            BoardState::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2")
+                .unwrap();
+        assert_eq!(b.to_fen(), expected.to_fen());
+        // passing is not a move, neither clock moves
+        assert_eq!((b.half_move_clock, b.full_move_number), (0, 2));
+        assert_eq!(b.zobrist_key, expected.zobrist_key);
+
+        b.unmake_null_move(&undo);
+        let original =
+            BoardState::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2")
+                .unwrap();
+        assert_eq!(b.to_fen(), original.to_fen());
+        assert_eq!(b.zobrist_key, original.zobrist_key);
+    }
+
     #[test]
     fn zobrist_swap_color() {
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
@@ -943,6 +1411,116 @@ mod tests {
         assert_eq!(b.board[9][8], Square::from(Piece::bishop(Black)));
     }
 
+    #[test]
+    fn correct_move_clocks() {
+        let b = BoardState::from_fen("8/1k6/8/8/7p/8/1K4P1/8 b - - 37 312").unwrap();
+        assert_eq!(b.half_move_clock, 37);
+        assert_eq!(b.full_move_number, 312);
+    }
+
+    #[test]
+    fn bad_fen_string_bad_move_clocks() {
+        assert!(
+            BoardState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - a 1")
+                .is_err()
+        );
+        assert!(
+            BoardState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 -1")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn to_fen_starting_pos() {
+        let b = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        assert_eq!(b.to_fen(), DEFAULT_FEN_STRING);
+    }
+
+    #[test]
+    fn to_fen_all_fields() {
+        let fen = "r3k2r/8/8/3pP3/8/8/8/4K2R w Kq d6 0 312";
+        assert_eq!(BoardState::from_fen(fen).unwrap().to_fen(), fen);
+        let fen = "8/1k6/8/8/7p/8/1K4P1/8 b - - 37 45";
+        assert_eq!(BoardState::from_fen(fen).unwrap().to_fen(), fen);
+    }
+
+    // Insufficient material tests
+
+    #[test]
+    fn insufficient_material_draws() {
+        for fen in [
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/5B2/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/8/6n1 b - - 0 1",
+            "8/2b5/4k3/8/8/3K4/5B2/8 w - - 0 1",
+            "8/8/4k3/2B5/8/3K4/5B2/b7 w - - 0 1",
+        ] {
+            assert!(BoardState::from_fen(fen)
+                .unwrap()
+                .is_insufficient_material());
+        }
+    }
+
+    #[test]
+    fn sufficient_material() {
+        for fen in [
+            DEFAULT_FEN_STRING,
+            "8/8/4k3/8/8/3K4/4P3/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/8/7r w - - 0 1",
+            "8/8/4k3/8/8/3K4/4Q3/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/4BB2/8 w - - 0 1",
+            "8/3b4/4k3/8/8/3K4/5B2/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/4NB2/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/4NN2/8 w - - 0 1",
+            "8/4n3/4k3/8/8/3K4/4N3/8 w - - 0 1",
+        ] {
+            assert!(!BoardState::from_fen(fen)
+                .unwrap()
+                .is_insufficient_material());
+        }
+    }
+
+    #[test]
+    fn move_to_string() {
+        let quiet = Move::new(Point(8, 6), Point(6, 6), None, Move::DOUBLE_PAWN_PUSH);
+        assert_eq!(quiet.to_string(), "e2e4");
+        assert!(quiet.is_double_pawn_push() && !quiet.is_capture());
+        let promotion = Move::new(Point(3, 2), Point(2, 3), Some(Queen), Move::CAPTURE);
+        assert_eq!(promotion.to_string(), "a7b8q");
+        assert_eq!(promotion.from(), Point(3, 2));
+        assert_eq!(promotion.to(), Point(2, 3));
+    }
+
+    #[test]
+    fn make_unmake_castle() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 3 10";
+        let mut b = BoardState::from_fen(fen).unwrap();
+        let castle = Move::new(Point(9, 6), Point(9, 8), None, Move::CASTLE);
+        let undo = b.make_move(castle, &zobrist_hasher);
+        assert_eq!(b.to_fen(), "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 4 10");
+        assert_eq!(b.white_king_location, Point(9, 8));
+        b.unmake_move(castle, &undo);
+        assert_eq!(b.to_fen(), fen);
+        assert_eq!(b.white_king_location, Point(9, 6));
+        assert_eq!(
+            b.zobrist_key,
+            BoardState::from_fen(fen).unwrap().zobrist_key
+        );
+    }
+
+    #[test]
+    fn make_unmake_promotion_capture() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let fen = "1r2k3/P7/8/8/8/8/8/4K3 w - - 7 30";
+        let mut b = BoardState::from_fen(fen).unwrap();
+        let promotion = Move::new(Point(3, 2), Point(2, 3), Some(Knight), Move::CAPTURE);
+        let undo = b.make_move(promotion, &zobrist_hasher);
+        assert_eq!(b.to_fen(), "1N2k3/8/8/8/8/8/8/4K3 b - - 0 30");
+        b.unmake_move(promotion, &undo);
+        assert_eq!(b.to_fen(), fen);
+    }
+
     #[test]
     fn bad_fen_string() {
         assert!(BoardState::from_fen("this isn't a fen string").is_err());
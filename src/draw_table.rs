@@ -1,7 +1,7 @@
 use crate::{board::BoardState, zobrist::ZobristKey};
 use std::collections::HashMap;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct DrawTable {
     pub table: HashMap<ZobristKey, u8>,
 }
@@ -3,43 +3,95 @@ pub use crate::board::{PieceColor::*, PieceKind::*};
 use crate::draw_table::DrawTable;
 pub use crate::evaluation::*;
 pub use crate::move_generation::*;
-pub use crate::search::{Search, KILLER_MOVE_PLY_SIZE, MAX_DEPTH};
-pub use crate::uci::send_to_gui;
-pub use crate::utils::out_of_time;
+use crate::pgn::{GameResult, PgnGame};
+use crate::polyglot::{BookSelection, PolyglotBook};
+use crate::san::move_to_san;
+pub use crate::search::{
+    MoveArray, Search, SearchLimits, SearchReport, SearchReporter, SearchSignals,
+    KILLER_MOVE_PLY_SIZE, MAX_DEPTH, MAX_HISTORY,
+};
+use crate::syzygy::{dtz_rank, Tablebases, Wdl, MAX_DTZ_RANK};
+use crate::transposition_table::{score_from_tt, Bound, TranspositionTable, DEFAULT_HASH_SIZE_MB};
+pub use crate::utils::send_to_gui;
 use crate::zobrist::ZobristHasher;
 use std::cmp::{max, min, Reverse};
-use std::sync::mpsc;
+
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-const MATE_SCORE: i32 = 100000;
+pub const MATE_SCORE: i32 = 100000;
+// a position the tablebases say is won, worse than any mate but better than any evaluation
+pub const TB_WIN_SCORE: i32 = MATE_SCORE / 2;
 const POS_INF: i32 = 9999999;
 const NEG_INF: i32 = -POS_INF;
+// a game is drawn once this many plies have been played without a capture or pawn move
+const FIFTY_MOVE_RULE_PLIES: u16 = 100;
 /*
     We want killer moves to be ordered behind all "good" captures, but still ahead of other moves
-    For our purposes a good capture is capturing any with a piece of lower value
+    For our purposes a good capture is one that does not lose material once all the recaptures
+    on the square are played out, according to the static exchange evaluation
 
-    Ex: capturing a pawn with a queen is a "bad" capture
-        capturing a queen with a pawn is a "good" capture
+    Ex: capturing a pawn defended by a pawn with a queen is a "bad" capture
+        capturing an undefended pawn with a queen is a "good" capture
 
     For this reason we give killer moves a 25, or ranked slightly between both types of captures
 */
 const KILLER_MOVE_SCORE: i32 = 25;
+// the move that refuted the opponent's last move elsewhere in the tree, tried after the killer moves
+// but still ahead of captures that lose material
+const COUNTERMOVE_SCORE: i32 = 22;
+// the best move stored in the transposition table is considered right after the pv move
+const TT_MOVE_SCORE: i32 = POS_INF - 1;
+/*
+    Late move reductions https://www.chessprogramming.org/Late_Move_Reductions
+
+    With good move ordering a cutoff almost always comes from one of the first few moves,
+    so quiet moves further down the list are searched to a lower depth first and only
+    searched again at full depth if they turn out better than expected
+*/
+const LMR_FULL_DEPTH_MOVES: usize = 3; // moves searched at full depth before reducing
+const LMR_MIN_DEPTH: u8 = 3; // closer to the horizon than this nothing is reduced
+const LMR_LATE_MOVES: usize = 8; // moves after this many are reduced by an extra ply
 
-type BoardSender = std::sync::mpsc::Sender<BoardState>;
+/*
+    Null move pruning, the null move search is reduced by at least NULL_MOVE_REDUCTION plies on
+    top of the usual one, plus a ply for every 6 plies of depth and for every NULL_MOVE_EVAL_MARGIN
+    the static evaluation is above beta, up to 3
+*/
+const NULL_MOVE_MIN_DEPTH: u8 = 3;
+const NULL_MOVE_REDUCTION: u8 = 2;
+const NULL_MOVE_EVAL_MARGIN: i32 = 200;
+
+/*
+    Aspiration windows https://www.chessprogramming.org/Aspiration_Windows
+
+    The score rarely changes much from one iteration to the next, so the root is searched with
+    a narrow window around the last score which cuts off more of the tree. When the score falls
+    outside the window the search is repeated with a window twice as wide on that side
+*/
+const ASPIRATION_WINDOW: i32 = 25;
+const ASPIRATION_MIN_DEPTH: u8 = 4; // shallow iterations are cheap and their scores jump around
 
 /*
     Capture extension, only search captures from here on to
     find a "quite" position
 */
 fn quiesce(
-    board: &BoardState,
+    board: &mut BoardState,
     mut alpha: i32,
     beta: i32,
     search_info: &mut Search,
     zobrist_hasher: &ZobristHasher,
 ) -> i32 {
     search_info.node_searched();
+    if board.is_insufficient_material() {
+        return 0;
+    }
+
     let stand_pat = get_evaluation(board);
     if stand_pat >= beta {
         return beta;
@@ -48,10 +100,17 @@ fn quiesce(
         alpha = stand_pat;
     }
 
-    let mut moves = generate_moves(board, MoveGenerationMode::CapturesOnly, zobrist_hasher);
-    moves.sort_unstable_by_key(|k| Reverse(k.order_heuristic));
-    for mov in moves {
-        let score = -quiesce(&mov, -beta, -alpha, search_info, zobrist_hasher);
+    // captures that lose material are almost never worth it, only search the rest
+    let mut moves: Vec<(i32, Move)> = generate_moves(board, MoveGenerationMode::CapturesOnly)
+        .into_iter()
+        .map(|mov| (order_heuristic(board, mov), mov))
+        .filter(|&(score, _)| score >= GOOD_CAPTURE_SCORE)
+        .collect();
+    moves.sort_unstable_by_key(|&(score, _)| Reverse(score));
+    for (_, mov) in moves {
+        let undo = board.make_move(mov, zobrist_hasher);
+        let score = -quiesce(board, -beta, -alpha, search_info, zobrist_hasher);
+        board.unmake_move(mov, &undo);
         if score >= beta {
             return beta;
         }
@@ -62,15 +121,25 @@ fn quiesce(
     alpha
 }
 
+/*
+    In zugzwang every move makes things worse, so passing would be the best move which makes
+    null move pruning unsound. This is common in endings where a side only has pawns left
+    https://www.chessprogramming.org/Zugzwang
+*/
+fn has_non_pawn_material(board: &BoardState, color: PieceColor) -> bool {
+    let pieces = board.pieces[color.index()];
+    [Queen, Rook, Bishop, Knight]
+        .iter()
+        .any(|kind| pieces[kind.index()] != 0)
+}
+
 /*
     Run a standard alpha beta search to try and find the best move
     Orders moves by piece value to attempt to improve search efficiency
 */
 #[allow(clippy::too_many_arguments)]
 fn alpha_beta_search(
-    start: Instant,
-    time_to_move_ms: u128,
-    board: &BoardState,
+    board: &mut BoardState,
     mut depth: u8,
     ply_from_root: i32,
     mut alpha: i32,
@@ -79,16 +148,27 @@ fn alpha_beta_search(
     allow_null: bool,
     zobrist_hasher: &ZobristHasher,
     draw_table: &mut DrawTable,
+    tt: &TranspositionTable,
 ) -> i32 {
-    // we are out of time, exit the search
-    if out_of_time(start, time_to_move_ms) {
+    search_info.clear_principle_variation(ply_from_root);
+
+    // we are out of time or were told to stop, exit the search
+    if search_info.should_stop() {
         return NEG_INF;
     }
 
     search_info.node_searched();
 
     // check for draw
-    if draw_table.is_threefold_repetition(board) {
+    if draw_table.is_threefold_repetition(board) || board.is_insufficient_material() {
+        return 0;
+    }
+
+    // fifty move rule, unless the move that got us here delivered checkmate
+    if board.half_move_clock >= FIFTY_MOVE_RULE_PLIES
+        && !(is_check(board, board.to_move)
+            && generate_moves(board, MoveGenerationMode::AllMoves).is_empty())
+    {
         return 0;
     }
 
@@ -113,34 +193,95 @@ fn alpha_beta_search(
         return alpha;
     }
 
-    // Null move pruning https://www.chessprogramming.org/Null_Move_Pruning
-    // With R = 2
-    if allow_null && depth >= 3 && !is_check(board, board.to_move) {
-        // allow this player to go again
-        let mut b = board.clone();
-        b.to_move = board.to_move.opposite();
-        let eval = -alpha_beta_search(
-            start,
-            time_to_move_ms,
-            &b,
-            depth - 3,
-            ply_from_root + 10, //hack for now but passing in a large ply ensures we don't overwrite the pv
-            -beta,
-            -beta + 1,
-            search_info,
-            false,
-            zobrist_hasher,
-            draw_table,
+    // Check if we have already searched this position deep enough to reuse the result
+    // only do this outside of the pv so the principle variation is still searched in full
+    let tt_entry = tt.probe(board.zobrist_key);
+    if let Some(entry) = tt_entry {
+        let is_pv_node = beta - alpha > 1;
+        if !is_pv_node && entry.depth >= depth {
+            let score = score_from_tt(entry.score, ply_from_root);
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cutoff {
+                draw_table.remove_board_from_draw_table(board);
+                return score;
+            }
+        }
+    }
+    let original_alpha = alpha;
+
+    // The tablebases know the result of this position, but only probe right after a capture or
+    // pawn move, as the result assumes the fifty move rule counter starts from zero
+    let wdl = search_info
+        .tablebases
+        .as_ref()
+        .filter(|tablebases| board.half_move_clock == 0 && tablebases.can_probe(board))
+        .and_then(|tablebases| tablebases.probe_wdl(board));
+    if let Some(wdl) = wdl {
+        search_info.tb_hits += 1;
+        let score = match wdl {
+            Wdl::Win => TB_WIN_SCORE - ply_from_root,
+            Wdl::Loss => -TB_WIN_SCORE + ply_from_root,
+            // a draw under the fifty move rule, but the opponent could still go wrong
+            Wdl::CursedWin => 1,
+            Wdl::BlessedLoss => -1,
+            Wdl::Draw => 0,
+        };
+        draw_table.remove_board_from_draw_table(board);
+        tt.store(
+            board.zobrist_key,
+            None,
+            score,
+            depth,
+            Bound::Exact,
+            ply_from_root,
         );
+        return score;
+    }
 
-        if eval >= beta {
-            // null move prune
-            draw_table.remove_board_from_draw_table(board);
-            return beta;
+    // Null move pruning https://www.chessprogramming.org/Null_Move_Pruning
+    // If passing the turn still leaves us above beta, an actual move almost certainly would too
+    if allow_null
+        && depth >= NULL_MOVE_MIN_DEPTH
+        && !is_check(board, board.to_move)
+        && has_non_pawn_material(board, board.to_move)
+    {
+        let static_eval = get_evaluation(board);
+        if static_eval >= beta {
+            // search deeper positions and positions far above beta with a larger reduction
+            let reduction = NULL_MOVE_REDUCTION
+                + depth / 6
+                + min((static_eval - beta) / NULL_MOVE_EVAL_MARGIN, 3) as u8;
+
+            // there is no move to refute with a countermove after passing
+            search_info.cur_line[ply_from_root as usize] = None;
+            let undo = board.make_null_move(zobrist_hasher);
+            let eval = -alpha_beta_search(
+                board,
+                depth.saturating_sub(reduction + 1),
+                ply_from_root + 1,
+                -beta,
+                -beta + 1,
+                search_info,
+                false,
+                zobrist_hasher,
+                draw_table,
+                tt,
+            );
+            board.unmake_null_move(&undo);
+
+            if eval >= beta {
+                // null move prune
+                draw_table.remove_board_from_draw_table(board);
+                return beta;
+            }
         }
     }
 
-    let mut moves = generate_moves(board, MoveGenerationMode::AllMoves, zobrist_hasher);
+    let moves = generate_moves(board, MoveGenerationMode::AllMoves);
     if moves.is_empty() {
         if is_check(board, board.to_move) {
             // checkmate
@@ -153,34 +294,43 @@ fn alpha_beta_search(
         return 0;
     }
 
-    // rank killer moves and pv moves
-    for mov in &mut moves {
-        if mov.last_move == search_info.pv_moves[ply_from_root as usize] {
-            // consider principle variation moves before anything else
-            mov.order_heuristic = POS_INF;
-        } else {
-            for i in 0..KILLER_MOVE_PLY_SIZE {
-                if mov.last_move == search_info.killer_moves[ply_from_root as usize][i] {
-                    // consider killer moves after considering "good" captures
-                    mov.order_heuristic = KILLER_MOVE_SCORE;
-                    break;
-                }
-            }
-        }
-    }
+    // rank killer moves, pv moves and the best move from a previous search of this position
+    let tt_move = tt_entry.and_then(|entry| entry.best_move);
+    let killer_moves = search_info.killer_moves[ply_from_root as usize];
+    let countermove = search_info.countermove(ply_from_root);
+    let pv_move = search_info.pv_move(ply_from_root);
+    let mut moves: Vec<(i32, Move)> = moves
+        .into_iter()
+        .map(|mov| {
+            let score = if pv_move == Some(mov) {
+                // consider principle variation moves before anything else
+                POS_INF
+            } else if tt_move == Some(mov) {
+                TT_MOVE_SCORE
+            } else if killer_moves.contains(&Some(mov)) {
+                // consider killer moves after considering "good" captures
+                KILLER_MOVE_SCORE
+            } else if countermove == Some(mov) {
+                COUNTERMOVE_SCORE
+            } else if mov.is_quiet() {
+                // the rest of the quiet moves go by their history, all of them behind the bad captures
+                search_info.history_score(board.to_move, mov) - MAX_HISTORY
+            } else {
+                order_heuristic(board, mov)
+            };
+            (score, mov)
+        })
+        .collect();
 
-    moves.sort_unstable_by_key(|k| Reverse(k.order_heuristic));
-    search_info.insert_into_cur_line(ply_from_root, &moves[0]);
-    if moves[0].order_heuristic != POS_INF {
-        search_info.set_principle_variation();
-    }
+    moves.sort_unstable_by_key(|&(score, _)| Reverse(score));
+    let (_, first_move) = moves[0];
+    search_info.insert_into_cur_line(ply_from_root, first_move);
 
     // do a full search with what we think is the best move
     // which should be the first move in the array
+    let undo = board.make_move(first_move, zobrist_hasher);
     let mut best_score = -alpha_beta_search(
-        start,
-        time_to_move_ms,
-        &moves[0],
+        board,
         depth - 1,
         ply_from_root + 1,
         -beta,
@@ -189,27 +339,66 @@ fn alpha_beta_search(
         true,
         zobrist_hasher,
         draw_table,
+        tt,
     );
+    board.unmake_move(first_move, &undo);
+    let mut best_move = Some(first_move);
 
     if best_score > alpha {
         if best_score >= beta {
             draw_table.remove_board_from_draw_table(board);
+            if !search_info.should_stop() {
+                // the first move was already the refutation, keep it ahead of the other quiet moves
+                if first_move.is_quiet() {
+                    search_info.quiet_cutoff(board.to_move, ply_from_root, depth, first_move, &[]);
+                }
+                tt.store(
+                    board.zobrist_key,
+                    best_move,
+                    best_score,
+                    depth,
+                    Bound::Lower,
+                    ply_from_root,
+                );
+            }
             return best_score;
         }
-        search_info.set_principle_variation();
+        search_info.update_principle_variation(ply_from_root, first_move);
         alpha = best_score;
     }
 
     // https://en.wikipedia.org/wiki/Principal_variation_search
     // try out all remaining moves with a reduced window
-    for mov in moves.iter().skip(1) {
+    let in_check = is_check(board, board.to_move);
+    let mut failed_quiets: Vec<Move> = Vec::new();
+    if first_move.is_quiet() {
+        failed_quiets.push(first_move);
+    }
+    for (move_number, &(order_score, mov)) in moves.iter().enumerate().skip(1) {
         search_info.insert_into_cur_line(ply_from_root, mov);
+        let undo = board.make_move(mov, zobrist_hasher);
+
+        // only quiet moves that don't involve a check and are only ranked by their history are reduced
+        let reduction = if depth >= LMR_MIN_DEPTH
+            && move_number >= LMR_FULL_DEPTH_MOVES
+            && order_score <= 0
+            && mov.is_quiet()
+            && !in_check
+            && !is_check(board, board.to_move)
+        {
+            if move_number >= LMR_LATE_MOVES && depth > LMR_MIN_DEPTH {
+                2
+            } else {
+                1
+            }
+        } else {
+            0
+        };
+
         // zero window search
         let mut score = -alpha_beta_search(
-            start,
-            time_to_move_ms,
-            mov,
-            depth - 1,
+            board,
+            depth - 1 - reduction,
             ply_from_root + 1,
             -alpha - 1,
             -alpha,
@@ -217,14 +406,29 @@ fn alpha_beta_search(
             true,
             zobrist_hasher,
             draw_table,
+            tt,
         );
 
+        if reduction > 0 && score > alpha {
+            // the reduced search beat alpha, check again at full depth before trusting it
+            score = -alpha_beta_search(
+                board,
+                depth - 1,
+                ply_from_root + 1,
+                -alpha - 1,
+                -alpha,
+                search_info,
+                true,
+                zobrist_hasher,
+                draw_table,
+                tt,
+            );
+        }
+
         if score > alpha && score < beta {
             // got a result outside our window, need to redo full search
             score = -alpha_beta_search(
-                start,
-                time_to_move_ms,
-                mov,
+                board,
                 depth - 1,
                 ply_from_root + 1,
                 -beta,
@@ -233,186 +437,1379 @@ fn alpha_beta_search(
                 true,
                 zobrist_hasher,
                 draw_table,
+                tt,
             );
 
             if score > alpha {
                 alpha = score;
             }
         }
+        board.unmake_move(mov, &undo);
 
         if score > best_score {
             if score >= beta {
-                // avoid inserting PV nodes or captures into the killer moves table
-                if mov.order_heuristic == 0 {
-                    search_info.insert_killer_move(ply_from_root, mov);
-                }
                 draw_table.remove_board_from_draw_table(board);
+                if !search_info.should_stop() {
+                    // captures are already ordered well enough without the quiet move heuristics
+                    if mov.is_quiet() {
+                        search_info.quiet_cutoff(
+                            board.to_move,
+                            ply_from_root,
+                            depth,
+                            mov,
+                            &failed_quiets,
+                        );
+                    }
+                    tt.store(
+                        board.zobrist_key,
+                        Some(mov),
+                        score,
+                        depth,
+                        Bound::Lower,
+                        ply_from_root,
+                    );
+                }
                 return score;
             }
-            search_info.set_principle_variation();
+            search_info.update_principle_variation(ply_from_root, mov);
             best_score = score;
+            best_move = Some(mov);
+        }
+        if mov.is_quiet() {
+            failed_quiets.push(mov);
         }
     }
 
     draw_table.remove_board_from_draw_table(board);
 
+    // a search that ran out of time returns garbage scores, don't remember them
+    if !search_info.should_stop() {
+        // if alpha was never raised none of the moves can be trusted to be the best
+        let (bound, best_move) = if best_score > original_alpha {
+            (Bound::Exact, best_move)
+        } else {
+            (Bound::Upper, None)
+        };
+        tt.store(
+            board.zobrist_key,
+            best_move,
+            best_score,
+            depth,
+            bound,
+            ply_from_root,
+        );
+    }
+
     best_score
 }
 
 /*
-    Interface to the alpha_beta function, works very similarly but returns a board state at the end
-    and also operates with a channel to send the best board state found so far
+    The outcome of a search
 */
+pub struct SearchResult {
+    pub best_move: Move,
+    pub ponder_move: Option<Move>, // the reply we expect from the opponent
+    pub score: Option<i32>, // from the point of view of the side to move, None if no iteration completed
+    pub nodes: u64,         // searched by every thread
+    pub from_book: bool,    // played from the opening book without searching
+}
+
+/*
+    One of the best lines found at the root of the search
+*/
+struct RootLine {
+    mov: Move,
+    score: i32,
+    pv: MoveArray,
+}
+
+/*
+    Interface to the alpha_beta function, works very similarly but returns the best move
+    found in the deepest completed iteration
+
+    With multi_pv set above one the best multi_pv moves are all searched with a full window
+    and reported to the GUI, the best of these is still the one that is returned
+
+    With tablebases covering the position only the moves that keep the best result are searched,
+    a won or lost position is played out with the move that zeroes the fifty move counter soonest
+    (or latest when lost) without searching, unless the search is for analysis
+
+    With threads set above one the extra threads help search the same position, see iterative_deepening
+
+    Returns None if there are no legal moves in this position
+*/
+#[allow(clippy::too_many_arguments)]
 pub fn get_best_move(
     board: &BoardState,
     draw_table: &mut DrawTable,
     start: Instant,
-    time_to_move_ms: u128,
-    tx: &BoardSender,
-) {
-    let mut cur_depth = 1;
+    limits: SearchLimits,
+    signals: Arc<SearchSignals>,
+    tt: &TranspositionTable,
+    multi_pv: usize,
+    tablebases: Option<Arc<Tablebases>>,
+    threads: usize,
+    reporter: Option<SearchReporter>,
+) -> Option<SearchResult> {
     let ply_from_root = 0;
-    let mut best_move: Option<BoardState> = None;
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH - 1).min(MAX_DEPTH - 1);
+    let multi_pv = max(multi_pv, 1);
 
-    let mut search_info = Search::new_search();
+    let mut search_info = Search::new_search(start, limits, signals);
+    search_info.reporter = reporter;
     let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+    tt.new_search();
 
-    let mut moves = generate_moves(board, MoveGenerationMode::AllMoves, &zobrist_hasher);
+    let mut board = board.clone();
+    let mut moves: Vec<(i32, Move)> = generate_moves(&board, MoveGenerationMode::AllMoves)
+        .into_iter()
+        .map(|mov| (order_heuristic(&board, mov), mov))
+        .collect();
 
-    while cur_depth < MAX_DEPTH {
-        let mut alpha = NEG_INF;
-        let beta = POS_INF;
+    let root_probe = tablebases
+        .as_ref()
+        .and_then(|tablebases| tablebases.probe_root(&board));
+    if let Some(mut root_moves) = root_probe {
+        search_info.tb_hits += 1;
+        // a move that repeats the position a third time is a draw no matter what the tables say
+        for (mov, dtz) in &mut root_moves {
+            let undo = board.make_move(*mov, &zobrist_hasher);
+            if draw_table.is_threefold_repetition(&board) {
+                *dtz = 0;
+            }
+            board.unmake_move(*mov, &undo);
+        }
+        let rank = |dtz: i32| dtz_rank(dtz, board.half_move_clock);
+        let best_rank = root_moves.iter().map(|&(_, dtz)| rank(dtz)).max()?;
+        // drawn positions still need a search to find the move most likely to trip up the opponent
+        let best_dtz = match best_rank {
+            0 => None,
+            _ => root_moves
+                .iter()
+                .filter(|&&(_, dtz)| rank(dtz) == best_rank)
+                .map(|&(_, dtz)| dtz)
+                .min(),
+        };
+        root_moves.retain(|&(_, dtz)| {
+            rank(dtz) == best_rank && best_dtz.is_none_or(|best_dtz| dtz == best_dtz)
+        });
+        moves.retain(|(_, mov)| root_moves.iter().any(|(root_move, _)| root_move == mov));
+
+        if let Some(dtz) = best_dtz {
+            if !limits.infinite && limits.mate.is_none() {
+                let score = match best_rank {
+                    MAX_DTZ_RANK => TB_WIN_SCORE - dtz,
+                    rank if rank == -MAX_DTZ_RANK => -TB_WIN_SCORE - dtz,
+                    _ => dtz.signum(),
+                };
+                let best_move = root_moves[0].0;
+                let mut pv = [None; MAX_DEPTH as usize];
+                pv[0] = Some(best_move);
+                search_info.report(&pv, 1, score, None, Bound::Exact);
+                return Some(SearchResult {
+                    best_move,
+                    ponder_move: None,
+                    score: Some(score),
+                    nodes: 0,
+                    from_book: false,
+                });
+            }
+        }
+    }
+    search_info.tablebases = tablebases;
+
+    /*
+        Lazy SMP, helper threads search the same position at the same time and share what they
+        find through the transposition table, which lets the main thread search deeper sooner
+        https://www.chessprogramming.org/Lazy_SMP
+
+        Only the main thread reports to the GUI and decides on the move, the helpers
+        are stopped as soon as it is done
+    */
+    let helper_signals = Arc::new(SearchSignals::default());
+    let best_lines = thread::scope(|scope| {
+        for thread_id in 1..max(threads, 1) {
+            let mut helper_info = search_info.new_helper(helper_signals.clone());
+            let mut board = board.clone();
+            let mut draw_table = draw_table.clone();
+            let mut moves = moves.clone();
+            let zobrist_hasher = &zobrist_hasher;
+            scope.spawn(move || {
+                iterative_deepening(
+                    &mut board,
+                    &mut draw_table,
+                    &mut moves,
+                    &mut helper_info,
+                    tt,
+                    1,
+                    max_depth,
+                    thread_id,
+                    zobrist_hasher,
+                );
+                helper_info.flush_nodes();
+            });
+        }
+
+        let best_lines = iterative_deepening(
+            &mut board,
+            draw_table,
+            &mut moves,
+            &mut search_info,
+            tt,
+            multi_pv,
+            max_depth,
+            0,
+            &zobrist_hasher,
+        );
+        helper_signals.stop.store(true, Ordering::Relaxed);
+        best_lines
+    });
+
+    // if we did not complete a single iteration, fall back to the best move as determined by the order_heuristic
+    // this can happen on very short time control situations
+    let (best_move, ponder_move, score) = match best_lines.into_iter().next() {
+        Some(line) => (
+            line.mov,
+            line.pv[ply_from_root as usize + 1],
+            Some(line.score),
+        ),
+        None => (moves.first()?.1, None, None),
+    };
+
+    // make sure the expected reply is actually a legal move
+    board.make_move(best_move, &zobrist_hasher);
+    let ponder_move = ponder_move.filter(|ponder_move| {
+        generate_moves(&board, MoveGenerationMode::AllMoves).contains(ponder_move)
+    });
+
+    Some(SearchResult {
+        best_move,
+        ponder_move,
+        score,
+        nodes: search_info.nodes(),
+        from_book: false,
+    })
+}
+
+/*
+    Search the root moves one depth at a time until the search is stopped or max_depth is
+    reached, returns the best lines of the deepest completed iteration
+
+    Thread 0 is the main thread and the only one to report its progress. Helper threads
+    start at alternating depths and try the root moves in a different order, so they
+    don't all spend their time on the same part of the tree
+*/
+#[allow(clippy::too_many_arguments)]
+fn iterative_deepening(
+    board: &mut BoardState,
+    draw_table: &mut DrawTable,
+    moves: &mut [(i32, Move)],
+    search_info: &mut Search,
+    tt: &TranspositionTable,
+    multi_pv: usize,
+    max_depth: u8,
+    thread_id: usize,
+    zobrist_hasher: &ZobristHasher,
+) -> Vec<RootLine> {
+    let main_thread = thread_id == 0;
+    let mut cur_depth = 1 + (thread_id % 2) as u8;
+    let ply_from_root = 0;
+    let mut best_lines: Vec<RootLine> = Vec::new();
+    while cur_depth <= max_depth {
         search_info.reset_search();
-        moves.sort_unstable_by_key(|k| Reverse(k.order_heuristic));
-        for mov in &moves {
-            // make an effort to exit once we are out of time
-            if out_of_time(start, time_to_move_ms) {
-                // if we have not found a move to send back, send back the best move as determined by the order_heuristic
-                // this can happen on very short time control situations
-                if best_move.is_none() {
-                    tx.send(moves[0].clone()).unwrap();
+        search_info.age_history();
+        moves.sort_by_key(|&(score, _)| Reverse(score));
+        if !main_thread && moves.len() > 2 {
+            let rotation = thread_id % (moves.len() - 1);
+            moves[1..].rotate_left(rotation);
+        }
+
+        // with a single line start from a narrow window around the score of the last iteration
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut window_alpha, mut window_beta) = match best_lines.first() {
+            Some(line) if multi_pv == 1 && cur_depth >= ASPIRATION_MIN_DEPTH => {
+                (line.score - delta, line.score + delta)
+            }
+            _ => (NEG_INF, POS_INF),
+        };
+
+        // a move that failed high is already known to be better than the best move we had
+        let mut failed_high: Option<Vec<RootLine>> = None;
+        let iteration_lines = loop {
+            let mut iteration_lines: Vec<RootLine> = Vec::with_capacity(multi_pv + 1);
+            let mut fail_high = None;
+            for (move_index, &(_, mov)) in moves.iter().enumerate() {
+                // only moves that could make it into the best lines need an exact score
+                let alpha = if iteration_lines.len() < multi_pv {
+                    window_alpha
+                } else {
+                    iteration_lines[multi_pv - 1].score
+                };
+
+                search_info.insert_into_cur_line(ply_from_root, mov);
+                let undo = board.make_move(mov, zobrist_hasher);
+                let evaluation = -alpha_beta_search(
+                    board,
+                    cur_depth - 1,
+                    ply_from_root + 1,
+                    -window_beta,
+                    -alpha,
+                    search_info,
+                    true,
+                    zobrist_hasher,
+                    draw_table,
+                    tt,
+                );
+                board.unmake_move(mov, &undo);
+
+                // the iteration was cut short, its results can't be trusted
+                if search_info.should_stop() {
+                    break;
+                }
+
+                if evaluation > alpha {
+                    //alpha raised, remember this line as one of the best lines
+                    search_info.update_principle_variation(ply_from_root, mov);
+                    let pv = search_info.principle_variation();
+                    let index = iteration_lines
+                        .iter()
+                        .position(|line| evaluation > line.score)
+                        .unwrap_or(iteration_lines.len());
+                    if index == 0 {
+                        // follow the best line first deeper in the tree
+                        search_info.pv_moves = pv;
+                    }
+                    iteration_lines.insert(
+                        index,
+                        RootLine {
+                            mov,
+                            score: evaluation,
+                            pv,
+                        },
+                    );
+                    iteration_lines.truncate(multi_pv);
+                    if evaluation >= window_beta {
+                        // the real score is somewhere above the window
+                        fail_high = Some(move_index);
+                        break;
+                    }
+                    if main_thread && multi_pv == 1 {
+                        search_info.report(&pv, cur_depth, evaluation, None, Bound::Exact);
+                    }
                 }
-                return;
             }
 
-            let evaluation = -alpha_beta_search(
-                start,
-                time_to_move_ms,
-                mov,
-                cur_depth - 1,
-                ply_from_root + 1,
-                -beta,
-                -alpha,
-                &mut search_info,
-                true,
-                &zobrist_hasher,
-                draw_table,
-            );
+            if search_info.should_stop() {
+                // the search again may already have an exact score for the move that failed high
+                break failed_high.map(|failed_high| match iteration_lines.first() {
+                    Some(line) if fail_high.is_none() && line.mov == failed_high[0].mov => {
+                        iteration_lines
+                    }
+                    _ => failed_high,
+                });
+            }
+
+            if let Some(move_index) = fail_high {
+                let line = &iteration_lines[0];
+                if main_thread {
+                    search_info.report(&line.pv, cur_depth, line.score, None, Bound::Lower);
+                }
+                // the move that failed high looks best, search it first with the wider window
+                moves[..=move_index].rotate_right(1);
+                delta *= 2;
+                window_beta = min(line.score + delta, POS_INF);
+                failed_high = Some(iteration_lines);
+            } else if iteration_lines.is_empty() && window_alpha > NEG_INF {
+                // none of the moves reached the window, the real score is somewhere below it
+                if main_thread {
+                    search_info.report(
+                        &best_lines[0].pv,
+                        cur_depth,
+                        window_alpha,
+                        None,
+                        Bound::Upper,
+                    );
+                }
+                delta *= 2;
+                window_alpha = max(window_alpha - delta, NEG_INF);
+            } else {
+                break Some(iteration_lines);
+            }
+            search_info.reset_search();
+        };
 
-            search_info.insert_into_cur_line(ply_from_root, mov);
+        best_lines = match iteration_lines {
+            Some(iteration_lines) => iteration_lines,
+            None => break,
+        };
+        // stopped while searching again after a fail high, the move that failed high is kept
+        if search_info.should_stop() {
+            break;
+        }
 
-            if evaluation > alpha && !out_of_time(start, time_to_move_ms) {
-                //alpha raised, remember this line as the pv
-                alpha = evaluation;
-                best_move = Some(mov.clone());
-                tx.send(mov.clone()).unwrap();
-                search_info.set_principle_variation();
-                send_search_info(&search_info, cur_depth, evaluation, start);
+        if main_thread && multi_pv > 1 {
+            for (i, line) in best_lines.iter().enumerate() {
+                search_info.report(&line.pv, cur_depth, line.score, Some(i + 1), Bound::Exact);
             }
         }
-        moves = generate_moves(board, MoveGenerationMode::AllMoves, &zobrist_hasher);
-        if let Some(b) = &best_move {
-            for mov in &mut moves {
-                if mov.last_move == b.last_move {
-                    // found the pv node
-                    mov.order_heuristic = POS_INF;
-                    break;
-                }
+
+        // a mate within the requested number of moves was found, no need to look any further
+        if let Some(mate) = search_info.limits.mate {
+            if best_lines
+                .first()
+                .is_some_and(|line| line.score >= MATE_SCORE - (2 * mate as i32 - 1))
+            {
+                break;
             }
         }
+
+        // search the best lines first next iteration, in the order they were found
+        for (score, mov) in moves.iter_mut() {
+            *score = match best_lines.iter().position(|line| line.mov == *mov) {
+                Some(index) => POS_INF - index as i32,
+                None => order_heuristic(board, *mov),
+            };
+        }
         cur_depth += 1;
     }
+
+    best_lines
 }
 
 /*
-    Send information about the current search status to the GUI
+    The public interface to the engine, for embedding it in other programs
+
+    Keeps track of the current position, the positions leading up to it for repetition
+    detection and the transposition table between searches. Cloning an engine is cheap,
+    clones share the same transposition table so a clone can be sent to another thread
+    to search while the original is used to stop it through the search signals.
 */
-fn send_search_info(search_info: &Search, depth: u8, eval: i32, start: Instant) {
-    let mut ponder_move = "".to_string();
-    for mov in &search_info.pv_moves {
-        if let Some(m) = mov {
-            ponder_move = format!("{} {}{}", ponder_move, m.0, m.1)
-        } else {
-            break;
+#[derive(Clone)]
+pub struct Engine {
+    board: BoardState,
+    draw_table: DrawTable,
+    tt: Arc<Mutex<TranspositionTable>>,
+    multi_pv: usize,
+    book: Option<Arc<PolyglotBook>>,
+    own_book: bool, // play moves from the book, when one is loaded, before searching
+    book_selection: BookSelection,
+    tablebases: Option<Arc<Tablebases>>,
+    threads: usize, // threads searching at the same time, sharing the transposition table
+    reporter: Option<SearchReporter>, // told about the progress of every search
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    // Create an engine set up for the start of a new game
+    pub fn new() -> Engine {
+        let board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let mut draw_table = DrawTable::new();
+        draw_table.add_board_to_draw_table(&board);
+        Engine {
+            board,
+            draw_table,
+            tt: Arc::new(Mutex::new(TranspositionTable::new(DEFAULT_HASH_SIZE_MB))),
+            multi_pv: 1,
+            book: None,
+            own_book: false,
+            book_selection: BookSelection::WeightedRandom,
+            tablebases: None,
+            threads: 1,
+            reporter: None,
         }
     }
 
-    let mate_window = 15;
-    if eval >= MATE_SCORE - mate_window {
-        // this player is threatening checkmate
-        send_to_gui(&format!(
-            "info pv{} depth {} nodes {} score mate {} time {}",
-            ponder_move,
-            depth,
-            search_info.nodes_searched,
-            (MATE_SCORE - eval + 1) / 2,
-            Instant::now().duration_since(start).as_millis()
-        ));
-    } else if eval <= -MATE_SCORE + mate_window {
-        // this player is getting matted
-        send_to_gui(&format!(
-            "info pv{} depth {} nodes {} score mate {} time {}",
-            ponder_move,
-            depth,
-            search_info.nodes_searched,
-            (MATE_SCORE + eval) / -2,
-            Instant::now().duration_since(start).as_millis()
-        ));
-    } else {
-        send_to_gui(&format!(
-            "info pv{} depth {} nodes {} score cp {} time {}",
-            ponder_move,
-            depth,
-            search_info.nodes_searched,
-            eval,
-            Instant::now().duration_since(start).as_millis()
-        ));
+    /*
+        Set up a position from a fen string and the moves played since, in long algebraic
+        notation (ex: e2e4, e7e8q), the same way the UCI position command describes a game
+
+        If the fen string or any of the moves are invalid the current position is kept
+    */
+    pub fn set_position(&mut self, fen: &str, moves: &[&str]) -> Result<(), &'static str> {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut board = BoardState::from_fen(fen)?;
+        let mut draw_table = DrawTable::new();
+        draw_table.add_board_to_draw_table(&board);
+        for mov in moves {
+            make_move(&mut board, mov, &zobrist_hasher)?;
+            draw_table.add_board_to_draw_table(&board);
+        }
+
+        self.board = board;
+        self.draw_table = draw_table;
+        Ok(())
+    }
+
+    pub fn board(&self) -> &BoardState {
+        &self.board
+    }
+
+    // Forget everything learned in previous games
+    pub fn new_game(&mut self) {
+        self.tt.lock().unwrap().clear();
+    }
+
+    // Resize the transposition table, this clears it as well
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        self.tt.lock().unwrap().resize(size_mb);
+    }
+
+    // Report and search this many of the best lines, only the best one is returned
+    pub fn set_multi_pv(&mut self, multi_pv: usize) {
+        self.multi_pv = max(multi_pv, 1);
+    }
+
+    // Search with this many threads
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = max(threads, 1);
+    }
+
+    // Use this opening book, or stop using a book when given None
+    pub fn set_book(&mut self, book: Option<PolyglotBook>) {
+        self.book = book.map(Arc::new);
+    }
+
+    // Play moves from the opening book while there is one for the current position
+    pub fn set_own_book(&mut self, own_book: bool) {
+        self.own_book = own_book;
+    }
+
+    pub fn set_book_selection(&mut self, book_selection: BookSelection) {
+        self.book_selection = book_selection;
+    }
+
+    // Probe these endgame tablebases during the search, or stop using them when given None
+    pub fn set_tablebases(&mut self, tablebases: Option<Tablebases>) {
+        self.tablebases = tablebases.map(Arc::new);
+    }
+
+    // Send the progress of every search to this reporter, or stop reporting when given None
+    pub fn set_reporter(&mut self, reporter: Option<SearchReporter>) {
+        self.reporter = reporter;
+    }
+
+    /*
+        Pick a move for the current position from the opening book, returns None
+        when the book is turned off or the position is not in the book
+    */
+    pub fn book_move(&self) -> Option<Move> {
+        let book = self.book.as_ref().filter(|_| self.own_book)?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        book.pick_move(&self.board, self.book_selection, &mut rng)
+    }
+
+    // Count the leaf nodes below each move in the current position to the given depth
+    pub fn perft_divide(&self, depth: usize) -> Vec<(Move, u64)> {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        perft_divide(&mut self.board.clone(), depth, &zobrist_hasher)
+    }
+
+    /*
+        Search the current position until one of the limits is reached, returns
+        None if there are no legal moves in this position
+
+        The progress of the search is given to the reporter, if one is set
+    */
+    pub fn search(&self, limits: SearchLimits) -> Option<SearchResult> {
+        self.search_with_signals(limits, Arc::new(SearchSignals::default()))
+    }
+
+    /*
+        Same as search, but the search can be stopped early or taken out of
+        ponder mode from another thread through the signals
+
+        A move from the opening book is played without searching, unless the search is
+        for analysis (infinite or looking for a mate) where a real evaluation is wanted
+    */
+    pub fn search_with_signals(
+        &self,
+        limits: SearchLimits,
+        signals: Arc<SearchSignals>,
+    ) -> Option<SearchResult> {
+        if !limits.infinite && limits.mate.is_none() {
+            if let Some(best_move) = self.book_move() {
+                return Some(SearchResult {
+                    best_move,
+                    ponder_move: None,
+                    score: None,
+                    nodes: 0,
+                    from_book: true,
+                });
+            }
+        }
+
+        let tt = self.tt.lock().unwrap();
+        let mut draw_table = self.draw_table.clone();
+        get_best_move(
+            &self.board,
+            &mut draw_table,
+            Instant::now(),
+            limits,
+            signals,
+            &tt,
+            self.multi_pv,
+            self.tablebases.clone(),
+            self.threads,
+            self.reporter.clone(),
+        )
     }
 }
 
+/*
+    Play a move given in long algebraic notation (ex: e2e4, e7e8q) on the board
+
+    Returns an error if the move is not legal in this position
+*/
+pub fn make_move(
+    board: &mut BoardState,
+    player_move: &str,
+    zobrist_hasher: &ZobristHasher,
+) -> Result<(), &'static str> {
+    let mov = generate_moves(board, MoveGenerationMode::AllMoves)
+        .into_iter()
+        .find(|mov| mov.to_string() == player_move)
+        .ok_or("Invalid move, not a legal move in this position")?;
+    board.make_move(mov, zobrist_hasher);
+    Ok(())
+}
+
 /*
     Play a game in the terminal where the engine plays against itself
+
+    The game is returned so it can be saved as a PGN, the search score of
+    every move is kept for the optional eval comments. The progress of every
+    search is given to the reporter, if there is one
 */
 pub fn play_game_against_self(
     b: &BoardState,
     max_moves: u8,
     time_to_move_ms: u128,
     simple_print: bool,
-) {
+    reporter: Option<SearchReporter>,
+) -> PgnGame {
     let show_board = |simple_print: bool, b: &BoardState| {
         if simple_print {
             b.simple_print_board()
         } else {
             b.pretty_print_board()
         }
+        println!("{}", b.to_fen());
     };
 
     let mut board = b.clone();
-    let draw_table: DrawTable = DrawTable::new();
+    let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+    let mut draw_table: DrawTable = DrawTable::new();
+    draw_table.add_board_to_draw_table(&board);
+    let tt = TranspositionTable::new(DEFAULT_HASH_SIZE_MB);
+    let player = format!("Walleye {}", env!("CARGO_PKG_VERSION"));
+    let mut game = PgnGame::new("Walleye self-play", &player, &player, &board);
     show_board(simple_print, &board);
+    let limits = SearchLimits {
+        movetime: Some(time_to_move_ms),
+        ..Default::default()
+    };
     for _ in 0..max_moves {
-        let (tx, rx) = mpsc::channel();
         let start = Instant::now();
-        let clone = board.clone();
+        let signals = Arc::new(SearchSignals::default());
         let mut draw_clone = draw_table.clone();
-        thread::spawn(move || get_best_move(&clone, &mut draw_clone, start, time_to_move_ms, &tx));
-        while !out_of_time(start, time_to_move_ms) {
-            if let Ok(b) = rx.try_recv() {
-                board = b;
-            } else {
-                thread::sleep(Duration::from_millis(1));
-            }
+        let result = match get_best_move(
+            &board,
+            &mut draw_clone,
+            start,
+            limits,
+            signals,
+            &tt,
+            1,
+            None,
+            1,
+            reporter.clone(),
+        ) {
+            Some(result) => result,
+            None => break,
+        };
+        let san = move_to_san(&board, result.best_move, &zobrist_hasher);
+        match board.to_move {
+            White => println!("{}. {}", board.full_move_number, san),
+            Black => println!("{}... {}", board.full_move_number, san),
         }
+        // the pgn eval is always from white's point of view
+        let eval = result.score.map(|score| match board.to_move {
+            White => score,
+            Black => -score,
+        });
+        game.add_move(san, eval);
+
+        board.make_move(result.best_move, &zobrist_hasher);
+        let repetition = draw_table.is_threefold_repetition(&board);
+        draw_table.add_board_to_draw_table(&board);
         show_board(simple_print, &board);
+
+        if let Some((result, termination)) = game_over(&board, repetition) {
+            println!("{}", termination);
+            game.set_result(result, termination);
+            return game;
+        }
+    }
+
+    game.set_result(GameResult::Unfinished, "Game stopped at the move limit");
+    game
+}
+
+/*
+    Check if the game is over after a move has been made, returns the result and the reason
+*/
+fn game_over(board: &BoardState, repetition: bool) -> Option<(GameResult, &'static str)> {
+    if generate_moves(board, MoveGenerationMode::AllMoves).is_empty() {
+        return Some(if !is_check(board, board.to_move) {
+            (GameResult::Draw, "Draw by stalemate")
+        } else if board.to_move == White {
+            (GameResult::BlackWins, "Black wins by checkmate")
+        } else {
+            (GameResult::WhiteWins, "White wins by checkmate")
+        });
+    }
+    if board.is_insufficient_material() {
+        return Some((GameResult::Draw, "Draw by insufficient material"));
+    }
+    if repetition {
+        return Some((GameResult::Draw, "Draw by threefold repetition"));
+    }
+    if board.half_move_clock >= FIFTY_MOVE_RULE_PLIES {
+        return Some((GameResult::Draw, "Draw by the fifty move rule"));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Keep every report the engine's searches send from now on
+    fn collect_reports(engine: &mut Engine) -> Arc<Mutex<Vec<SearchReport>>> {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let collected = reports.clone();
+        engine.set_reporter(Some(Arc::new(move |report: &SearchReport| {
+            collected.lock().unwrap().push(report.clone())
+        })));
+        reports
+    }
+
+    #[test]
+    fn move_clocks_updated() {
+        let mut board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        make_move(&mut board, "g1f3", &zobrist_hasher).unwrap();
+        assert_eq!((board.half_move_clock, board.full_move_number), (1, 1));
+        make_move(&mut board, "g8f6", &zobrist_hasher).unwrap();
+        assert_eq!((board.half_move_clock, board.full_move_number), (2, 2));
+        make_move(&mut board, "e2e4", &zobrist_hasher).unwrap();
+        assert_eq!((board.half_move_clock, board.full_move_number), (0, 2));
+        make_move(&mut board, "f6e4", &zobrist_hasher).unwrap();
+        assert_eq!((board.half_move_clock, board.full_move_number), (0, 3));
+    }
+
+    #[test]
+    fn en_passant_capture_parsed_correctly_black() {
+        let mut board = BoardState::from_fen("8/1k6/8/8/7p/8/1K4P1/8 w - - 0 1").unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        make_move(&mut board, "g2g4", &zobrist_hasher).unwrap();
+        make_move(&mut board, "h4g3", &zobrist_hasher).unwrap();
+        assert_eq!(board.board[7][8], Square::from(Piece::pawn(Black)));
+
+        let mut pawn_count = 0;
+        for i in BOARD_START..BOARD_END {
+            for j in BOARD_START..BOARD_END {
+                if let Square::Full(Piece { kind, .. }) = board.board[i][j] {
+                    if kind == Pawn {
+                        pawn_count += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(pawn_count, 1);
+    }
+
+    #[test]
+    fn en_passant_capture_parsed_correctly_white() {
+        let mut board = BoardState::from_fen("8/1k4p1/8/5P2/8/8/1K6/8 b - - 0 1").unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        make_move(&mut board, "g7g5", &zobrist_hasher).unwrap();
+        make_move(&mut board, "f5g6", &zobrist_hasher).unwrap();
+        assert_eq!(board.board[4][8], Square::from(Piece::pawn(White)));
+
+        let mut pawn_count = 0;
+        for i in BOARD_START..BOARD_END {
+            for j in BOARD_START..BOARD_END {
+                if let Square::Full(Piece { kind, .. }) = board.board[i][j] {
+                    if kind == Pawn {
+                        pawn_count += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(pawn_count, 1);
+    }
+
+    #[test]
+    fn game_is_draw_three_fold_repetition() {
+        let mut engine = Engine::new();
+        let moves: Vec<&str> = "a3b3 a6b6 b3c4 b6c6 c4d4 c6d6 d4c4 d6c6 c4d4 c6d6 d4c4 d6c6"
+            .split(' ')
+            .collect();
+        engine
+            .set_position("8/8/k7/p7/P7/K7/8/8 w - - 0 1", &moves)
+            .unwrap();
+        let count = engine.draw_table.table.get(&engine.board.zobrist_key);
+        assert_eq!(*count.unwrap(), 3);
+    }
+
+    #[test]
+    fn make_move_invalid_notation() {
+        let mut board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        for mov in ["", "e2", "e2e4e", "i2i4", "e2e9", "e3e4", "e7e5", "é2e4"] {
+            assert!(make_move(&mut board, mov, &zobrist_hasher).is_err());
+        }
+        assert_eq!(board.to_fen(), DEFAULT_FEN_STRING);
+    }
+
+    #[test]
+    fn set_position_invalid_keeps_position() {
+        let mut engine = Engine::new();
+        engine.set_position(DEFAULT_FEN_STRING, &["e2e4"]).unwrap();
+        let fen = engine.board().to_fen();
+        assert!(engine.set_position("not a fen", &[]).is_err());
+        assert!(engine
+            .set_position(DEFAULT_FEN_STRING, &["e2e4", "e2e4"])
+            .is_err());
+        assert_eq!(engine.board().to_fen(), fen);
+    }
+
+    #[test]
+    fn engine_finds_mate_in_one() {
+        let mut engine = Engine::new();
+        engine
+            .set_position("6k1/5ppp/8/8/8/8/8/3QK3 w - - 0 1", &[])
+            .unwrap();
+        let limits = SearchLimits {
+            depth: Some(3),
+            ..Default::default()
+        };
+        let result = engine.search(limits).unwrap();
+        assert_eq!(result.best_move.to_string(), "d1d8");
+    }
+
+    #[test]
+    fn engine_finds_mate_in_one_with_threads() {
+        let mut engine = Engine::new();
+        engine.set_threads(3);
+        engine
+            .set_position("6k1/5ppp/8/8/8/8/8/3QK3 w - - 0 1", &[])
+            .unwrap();
+        let limits = SearchLimits {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let result = engine.search(limits).unwrap();
+        assert_eq!(result.best_move.to_string(), "d1d8");
+        assert_eq!(result.score, Some(MATE_SCORE - 1));
+    }
+
+    #[test]
+    fn null_move_skipped_with_only_pawns() {
+        let b = BoardState::from_fen("8/8/1p1k4/1P1p4/3P1K2/8/8/8 w - - 0 1").unwrap();
+        assert!(!has_non_pawn_material(&b, White));
+        assert!(!has_non_pawn_material(&b, Black));
+
+        let b = BoardState::from_fen("8/8/1p1k4/1P1p4/3P1K2/8/8/7N w - - 0 1").unwrap();
+        assert!(has_non_pawn_material(&b, White));
+        assert!(!has_non_pawn_material(&b, Black));
+    }
+
+    #[test]
+    fn first_move_cutoff_updates_quiet_heuristics() {
+        let mut board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut draw_table = DrawTable::new();
+        draw_table.add_board_to_draw_table(&board);
+        let tt = TranspositionTable::new(1);
+        let mut search_info = Search::new_search(
+            Instant::now(),
+            SearchLimits::default(),
+            Arc::new(SearchSignals::default()),
+        );
+
+        // every move beats a window this low, so the first one tried is a cutoff
+        let score = alpha_beta_search(
+            &mut board,
+            3,
+            0,
+            -MATE_SCORE,
+            -MATE_SCORE + 1,
+            &mut search_info,
+            false,
+            &zobrist_hasher,
+            &mut draw_table,
+            &tt,
+        );
+        assert!(score > -MATE_SCORE);
+        let killer = search_info.killer_moves[0][0].unwrap();
+        assert!(killer.is_quiet());
+        assert!(search_info.history_score(White, killer) > 0);
+    }
+
+    #[test]
+    fn late_move_reductions_keep_quiet_tactics() {
+        // Win at Chess #8, the winning rook move is quiet and not a check so it and the quiet
+        // threats that follow are all candidates for reduction
+        let fen = "r4q1k/p2bR1rp/2p2Q1N/5p2/5p2/2P5/PP3PPP/R5K1 w - - 0 1";
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut engine = Engine::new();
+        engine.set_position(fen, &[]).unwrap();
+        let rf7 = generate_moves(engine.board(), MoveGenerationMode::AllMoves)
+            .into_iter()
+            .find(|mov| mov.to_string() == "e7f7")
+            .unwrap();
+        assert!(rf7.is_quiet());
+        let mut board = engine.board().clone();
+        board.make_move(rf7, &zobrist_hasher);
+        assert!(!is_check(&board, board.to_move));
+
+        let result = engine
+            .search(SearchLimits {
+                depth: Some(6),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(result.best_move, rf7);
+        assert!(result.score.unwrap() > 500);
+    }
+
+    #[test]
+    fn reported_pvs_are_legal() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let fens = [
+            DEFAULT_FEN_STRING,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        ];
+        for fen in fens {
+            let mut engine = Engine::new();
+            let reports = collect_reports(&mut engine);
+            engine.set_multi_pv(2);
+            engine.set_position(fen, &[]).unwrap();
+            let limits = SearchLimits {
+                depth: Some(6),
+                ..Default::default()
+            };
+            engine.search(limits).unwrap();
+            engine.set_multi_pv(1);
+            engine.search(limits).unwrap();
+
+            let reported = reports.lock().unwrap().clone();
+            assert!(!reported.is_empty());
+            for SearchReport { pv, .. } in reported {
+                let mut board = engine.board().clone();
+                for &mov in &pv {
+                    let moves = generate_moves(&board, MoveGenerationMode::AllMoves);
+                    assert!(
+                        moves.contains(&mov),
+                        "{} is not legal in {} for pv {:?}",
+                        mov,
+                        board.to_fen(),
+                        pv.iter().map(|m| m.to_string()).collect::<Vec<_>>()
+                    );
+                    board.make_move(mov, &zobrist_hasher);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ponder_search_ignores_move_time_until_ponderhit() {
+        let mut engine = Engine::new();
+        let board = engine.board().clone();
+        let (report_time, report_times) = std::sync::mpsc::channel();
+        let report_time = Mutex::new(report_time);
+        engine.set_reporter(Some(Arc::new(move |report: &SearchReport| {
+            let _ = report_time.lock().unwrap().send(report.time_ms);
+        })));
+        let signals = Arc::new(SearchSignals::default());
+        signals.ponder.store(true, Ordering::Relaxed);
+        let limits = SearchLimits {
+            movetime: Some(100),
+            ..Default::default()
+        };
+        let search = {
+            let signals = signals.clone();
+            thread::spawn(move || engine.search_with_signals(limits, signals))
+        };
+
+        // the opponent is still thinking, the search goes on well past the move time
+        let timeout = Duration::from_secs(30);
+        while report_times.recv_timeout(timeout).unwrap() < 300 {}
+        assert!(!search.is_finished());
+
+        // ponderhit, the clock starts now
+        let ponderhit = Instant::now();
+        signals.ponder.store(false, Ordering::Relaxed);
+        let result = search.join().unwrap().unwrap();
+        let elapsed = Instant::now().duration_since(ponderhit);
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_secs(10));
+        assert!(generate_moves(&board, MoveGenerationMode::AllMoves).contains(&result.best_move));
+    }
+
+    #[test]
+    fn stopped_infinite_search_returns_last_iteration() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut engine = Engine::new();
+        let reports = collect_reports(&mut engine);
+        let collector = engine.reporter.clone().unwrap();
+
+        // once depth 3 is under way the search waits until it has been told to stop
+        let (started, depth_started) = std::sync::mpsc::channel();
+        let (stopped, wait_for_stop) = std::sync::mpsc::channel::<()>();
+        let handshake = Mutex::new(Some((started, wait_for_stop)));
+        engine.set_reporter(Some(Arc::new(move |report: &SearchReport| {
+            collector(report);
+            if report.depth == 3 {
+                if let Some((started, wait_for_stop)) = handshake.lock().unwrap().take() {
+                    started.send(()).unwrap();
+                    wait_for_stop.recv().unwrap();
+                }
+            }
+        })));
+        engine.set_position(fen, &[]).unwrap();
+        let signals = Arc::new(SearchSignals::default());
+        let limits = SearchLimits {
+            infinite: true,
+            ..Default::default()
+        };
+        let search = {
+            let signals = signals.clone();
+            thread::spawn(move || engine.search_with_signals(limits, signals))
+        };
+
+        depth_started.recv_timeout(Duration::from_secs(30)).unwrap();
+        assert!(!search.is_finished());
+        let stop = Instant::now();
+        signals.stop.store(true, Ordering::Relaxed);
+        stopped.send(()).unwrap();
+        let result = search.join().unwrap().unwrap();
+        assert!(Instant::now().duration_since(stop) < Duration::from_secs(5));
+
+        // depth 3 was cut short, the best line of depth 2 is the last one reported for it
+        let reported = reports.lock().unwrap().clone();
+        let last_completed = reported.iter().rev().find(|line| line.depth == 2).unwrap();
+        assert_eq!(last_completed.bound, Bound::Exact);
+        assert_eq!(result.best_move, last_completed.pv[0]);
+        assert_eq!(result.score, Some(last_completed.score));
+        assert_eq!(result.ponder_move, last_completed.pv.get(1).copied());
+    }
+
+    #[test]
+    fn search_returns_ponder_move_from_pv() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut engine = Engine::new();
+        let reports = collect_reports(&mut engine);
+        engine
+            .set_position(
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                &[],
+            )
+            .unwrap();
+        let result = engine
+            .search(SearchLimits {
+                depth: Some(5),
+                ..Default::default()
+            })
+            .unwrap();
+        let pv = reports.lock().unwrap().last().unwrap().pv.clone();
+        assert_eq!(pv[0], result.best_move);
+        let ponder_move = result.ponder_move.unwrap();
+        assert_eq!(pv[1], ponder_move);
+        let mut board = engine.board().clone();
+        board.make_move(result.best_move, &zobrist_hasher);
+        assert!(generate_moves(&board, MoveGenerationMode::AllMoves).contains(&ponder_move));
+    }
+
+    #[test]
+    fn engine_search_no_legal_moves() {
+        let mut engine = Engine::new();
+        engine
+            .set_position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", &[])
+            .unwrap();
+        let limits = SearchLimits {
+            depth: Some(3),
+            ..Default::default()
+        };
+        assert!(engine.search(limits).is_none());
+    }
+
+    #[test]
+    fn engine_search_mate_no_legal_moves() {
+        let mut engine = Engine::new();
+        engine
+            .set_position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", &[])
+            .unwrap();
+        let limits = SearchLimits {
+            mate: Some(2),
+            depth: Some(4),
+            ..Default::default()
+        };
+        assert!(engine.search(limits).is_none());
+    }
+
+    #[test]
+    fn engine_reports_multi_pv_lines() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut engine = Engine::new();
+        let reports = collect_reports(&mut engine);
+        engine.set_multi_pv(3);
+        engine
+            .set_position("6k1/5ppp/8/8/8/8/5PPP/3QR1K1 w - - 0 1", &[])
+            .unwrap();
+        let depth = 4;
+        let limits = SearchLimits {
+            depth: Some(depth),
+            ..Default::default()
+        };
+        let result = engine.search(limits).unwrap();
+        let reported = reports.lock().unwrap().clone();
+
+        // the last three lines sent are the final iteration, tagged 1 to 3
+        let lines = &reported[reported.len() - 3..];
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line.multi_pv, Some(i + 1));
+            assert_eq!(line.depth, depth);
+            assert_eq!(line.bound, Bound::Exact);
+        }
+        let first_moves: Vec<Move> = lines.iter().map(|line| line.pv[0]).collect();
+        assert_eq!(first_moves[0], result.best_move);
+        assert!(first_moves[0] != first_moves[1] && first_moves[1] != first_moves[2]);
+        assert!(first_moves[0] != first_moves[2]);
+        assert!(lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // both mates in one are found, every line has a pv of its own that starts with its move
+        assert_eq!(lines[0].score, MATE_SCORE - 1);
+        assert_eq!(lines[1].score, MATE_SCORE - 1);
+        for line in lines {
+            let mut board = engine.board().clone();
+            for &mov in &line.pv {
+                assert!(generate_moves(&board, MoveGenerationMode::AllMoves).contains(&mov));
+                board.make_move(mov, &zobrist_hasher);
+            }
+        }
+    }
+
+    // Search a position until the reports so far meet the condition, the search is stopped there
+    fn search_until(
+        fen: &str,
+        condition: impl Fn(&[SearchReport]) -> bool + Send + Sync + 'static,
+    ) -> (SearchResult, Vec<SearchReport>) {
+        let mut engine = Engine::new();
+        engine.set_position(fen, &[]).unwrap();
+        let signals = Arc::new(SearchSignals::default());
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let (collected, stop) = (reports.clone(), signals.clone());
+        engine.set_reporter(Some(Arc::new(move |report: &SearchReport| {
+            let mut collected = collected.lock().unwrap();
+            if stop.stop.load(Ordering::Relaxed) {
+                return;
+            }
+            collected.push(report.clone());
+            if condition(&collected) {
+                stop.stop.store(true, Ordering::Relaxed);
+            }
+        })));
+        let limits = SearchLimits {
+            infinite: true,
+            ..Default::default()
+        };
+        let result = engine.search_with_signals(limits, signals).unwrap();
+        let reported = reports.lock().unwrap().clone();
+        (result, reported)
+    }
+
+    #[test]
+    fn aspiration_window_researches_after_fail_high() {
+        let fen = "2r3k1/p4p2/3Rp2p/1p2P1pK/8/1P4P1/P3Q2P/1q6 b - - 0 1";
+        let limits = SearchLimits {
+            depth: Some(6),
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        let reports = collect_reports(&mut engine);
+        engine.set_position(fen, &[]).unwrap();
+        let result = engine.search(limits).unwrap();
+        let reported = reports.lock().unwrap().clone();
+
+        // the mate in three is found once the window is already set, so the search fails high
+        // and the window is widened until the move that failed high gets an exact score
+        let first_lower = reported
+            .iter()
+            .position(|line| line.bound == Bound::Lower)
+            .unwrap();
+        let failed_high = &reported[first_lower];
+        let researched = reported[first_lower..]
+            .iter()
+            .position(|line| line.bound == Bound::Exact)
+            .map(|index| &reported[first_lower + index])
+            .unwrap();
+        assert_eq!(failed_high.pv[0].to_string(), "b1f5");
+        assert_eq!(researched.depth, failed_high.depth);
+        assert_eq!(researched.pv[0], failed_high.pv[0]);
+        assert!(researched.score > failed_high.score);
+        let last = reported.last().unwrap();
+        assert_eq!(last.bound, Bound::Exact);
+        assert_eq!(last.score, MATE_SCORE - 5);
+
+        // stopped right after b1f5 failed high, it is kept over the move of the last iteration
+        let (stopped_result, stopped_reports) = search_until(fen, |reported| {
+            reported.last().unwrap().bound == Bound::Lower
+        });
+        let failed_high = stopped_reports.last().unwrap();
+        assert_eq!(stopped_result.best_move, failed_high.pv[0]);
+        assert_eq!(stopped_result.score, Some(failed_high.score));
+        assert_ne!(stopped_reports[0].pv[0], failed_high.pv[0]);
+
+        // stopped once searching again gave b1f5 an exact score, that line is kept
+        let (stopped_result, stopped_reports) = search_until(fen, |reported| {
+            let last = reported.last().unwrap();
+            last.bound == Bound::Exact
+                && reported
+                    .iter()
+                    .any(|line| line.bound == Bound::Lower && line.depth == last.depth)
+        });
+        let researched = stopped_reports.last().unwrap();
+        assert_eq!(stopped_result.best_move, researched.pv[0]);
+        assert_eq!(stopped_result.score, Some(researched.score));
+        assert_eq!(stopped_result.ponder_move, researched.pv.get(1).copied());
+        assert!(stopped_result.ponder_move.is_some());
+
+        // searching more than one line always uses a full window
+        let mut full_window = Engine::new();
+        let reports = collect_reports(&mut full_window);
+        full_window.set_multi_pv(2);
+        full_window.set_position(fen, &[]).unwrap();
+        let full_window_result = full_window.search(limits).unwrap();
+        let reported = reports.lock().unwrap().clone();
+        assert!(reported.iter().all(|line| line.bound == Bound::Exact));
+        assert_eq!(result.best_move, full_window_result.best_move);
+    }
+
+    #[test]
+    fn play_self_records_game() {
+        let board = BoardState::from_fen("6k1/5ppp/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let game = play_game_against_self(&board, 10, 200, true, None);
+        assert_eq!(game.result, GameResult::WhiteWins);
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.moves[0].san, "Qd8#");
+        assert!(game.moves[0].eval.is_some_and(|eval| eval > 0));
+        let pgn = game.to_pgn(false);
+        assert!(pgn.contains("[FEN \"6k1/5ppp/8/8/8/8/8/3QK3 w - - 0 1\"]"));
+        assert!(pgn.ends_with("1. Qd8# {White wins by checkmate} 1-0\n"));
+    }
+
+    #[test]
+    fn game_over_draws() {
+        let stalemate = BoardState::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(
+            game_over(&stalemate, false),
+            Some((GameResult::Draw, "Draw by stalemate"))
+        );
+        let board = BoardState::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 100 80").unwrap();
+        assert_eq!(
+            game_over(&board, false),
+            Some((GameResult::Draw, "Draw by the fifty move rule"))
+        );
+        let board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        assert_eq!(game_over(&board, false), None);
+        assert_eq!(
+            game_over(&board, true),
+            Some((GameResult::Draw, "Draw by threefold repetition"))
+        );
+    }
+
+    #[test]
+    fn engine_plays_book_moves() {
+        // a book with only 1. e4 (e2 is square 12 and e4 is square 28 in Polyglot)
+        let start = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let mut bytes = crate::polyglot::polyglot_key(&start).to_be_bytes().to_vec();
+        bytes.extend((12u16 << 6 | 28).to_be_bytes());
+        bytes.extend([0, 1, 0, 0, 0, 0]);
+
+        let mut engine = Engine::new();
+        engine.set_book(Some(PolyglotBook::from_bytes(&bytes).unwrap()));
+        assert!(engine.book_move().is_none());
+        engine.set_own_book(true);
+        let limits = SearchLimits {
+            depth: Some(1),
+            ..Default::default()
+        };
+        let result = engine.search(limits).unwrap();
+        assert_eq!(result.best_move.to_string(), "e2e4");
+        assert_eq!(result.score, None);
+        assert!(result.from_book);
+
+        // out of book, fall back to searching
+        engine.set_position(DEFAULT_FEN_STRING, &["d2d4"]).unwrap();
+        assert!(engine.book_move().is_none());
+        let result = engine.search(limits).unwrap();
+        assert!(result.score.is_some());
+        assert!(!result.from_book);
+    }
+
+    #[test]
+    fn engine_uses_tablebases() {
+        // KQvK tables where white to move always wins, in 11 plies when not zeroing
+        let dir = std::env::temp_dir().join(format!("walleye_engine_tb_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pieces = [1, 0, 0x66, 0x55, 0xee, 0];
+        let wdl = [
+            [0x71, 0xe8, 0x23, 0x5d].as_slice(),
+            &pieces,
+            &[0x80, 4, 0x80, 0],
+        ]
+        .concat();
+        let dtz = [[0xd7, 0x66, 0x0c, 0xa5].as_slice(), &pieces, &[0x80, 5]].concat();
+        std::fs::write(dir.join("KQvK.rtbw"), wdl).unwrap();
+        std::fs::write(dir.join("KQvK.rtbz"), dtz).unwrap();
+        let tablebases = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        let mut engine = Engine::new();
+        engine.set_tablebases(Some(tablebases));
+        let limits = SearchLimits {
+            depth: Some(2),
+            ..Default::default()
+        };
+
+        // every move but hanging the queen wins, mating right away is the fastest
+        engine
+            .set_position("7k/8/6K1/8/8/8/8/Q7 w - - 0 1", &[])
+            .unwrap();
+        let result = engine.search(limits).unwrap();
+        assert_eq!(result.best_move.to_string(), "a1a8");
+        assert_eq!(result.score, Some(TB_WIN_SCORE - 1));
+
+        // the rook can be taken, which reaches a position the tables cover
+        engine
+            .set_position("7k/8/8/8/8/8/r7/Q3K3 w - - 0 1", &[])
+            .unwrap();
+        let result = engine.search(limits).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(result.best_move.to_string(), "a1a2");
+        assert_eq!(result.score, Some(TB_WIN_SCORE - 1));
     }
 }
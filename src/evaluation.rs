@@ -1,3 +1,4 @@
+use crate::bitboard;
 pub use crate::board::*;
 pub use crate::board::{PieceColor::*, PieceKind::*};
 
@@ -214,20 +215,19 @@ pub fn get_evaluation(board: &BoardState) -> i32 {
     let mut black_eg = 0;
     let mut game_phase = 0;
 
-    for row in BOARD_START..BOARD_END {
-        for col in BOARD_START..BOARD_END {
-            if let Square::Full(Piece { color, kind }) = board.board[row][col] {
-                game_phase += game_phase_val(kind);
-                if color == White {
-                    white_mg +=
-                        mg_table(kind)[row - BOARD_START][col - BOARD_START] + mg_piece_val(kind);
-                    white_eg +=
-                        eg_table(kind)[row - BOARD_START][col - BOARD_START] + eg_piece_val(kind);
-                } else {
-                    black_mg += mg_table(kind)[9 - row][col - BOARD_START] + mg_piece_val(kind);
-                    black_eg += eg_table(kind)[9 - row][col - BOARD_START] + eg_piece_val(kind);
-                }
-            }
+    for kind in [Pawn, Knight, Bishop, Rook, Queen, King] {
+        for square in bitboard::squares(board.pieces[White.index()][kind.index()]) {
+            let (row, col) = (square / 8, square % 8);
+            game_phase += game_phase_val(kind);
+            white_mg += mg_table(kind)[row][col] + mg_piece_val(kind);
+            white_eg += eg_table(kind)[row][col] + eg_piece_val(kind);
+        }
+        // the tables are from white's point of view, so flip them for black
+        for square in bitboard::squares(board.pieces[Black.index()][kind.index()]) {
+            let (row, col) = (7 - square / 8, square % 8);
+            game_phase += game_phase_val(kind);
+            black_mg += mg_table(kind)[row][col] + mg_piece_val(kind);
+            black_eg += eg_table(kind)[row][col] + eg_piece_val(kind);
         }
     }
 
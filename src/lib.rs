@@ -0,0 +1,36 @@
+//! Walleye is a UCI chess engine, this library exposes its move generator and search
+//! so they can be embedded in other programs.
+//!
+//! ```
+//! use walleye::{Engine, SearchLimits};
+//!
+//! let mut engine = Engine::new();
+//! engine
+//!     .set_position(walleye::board::DEFAULT_FEN_STRING, &["e2e4", "e7e5"])
+//!     .unwrap();
+//! let limits = SearchLimits {
+//!     depth: Some(3),
+//!     ..Default::default()
+//! };
+//! let result = engine.search(limits).unwrap();
+//! println!("best move: {}", result.best_move);
+//! ```
+
+pub mod bitboard;
+pub mod board;
+pub mod draw_table;
+pub mod engine;
+pub mod evaluation;
+pub mod move_generation;
+pub mod perft_suite;
+pub mod pgn;
+pub mod polyglot;
+pub mod san;
+pub mod search;
+pub mod syzygy;
+pub mod transposition_table;
+pub mod utils;
+pub mod zobrist;
+
+pub use engine::{Engine, SearchResult};
+pub use search::SearchLimits;
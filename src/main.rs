@@ -1,16 +1,9 @@
 extern crate clap;
 use clap::{App, Arg};
-use std::{cmp::max, time::Instant};
-mod board;
-mod draw_table;
-mod engine;
-mod evaluation;
-mod move_generation;
-mod search;
+use std::{cmp::max, fs, process, time::Instant};
+use walleye::{board, engine, move_generation, perft_suite, pgn, search, zobrist};
 mod time_control;
 mod uci;
-mod utils;
-mod zobrist;
 
 /*
     A custom memory allocator with better performance
@@ -23,6 +16,28 @@ mod zobrist;
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+/*
+    Positions searched by the search benchmark, the perft positions from
+    https://www.chessprogramming.org/Perft_Results and a few from the Stockfish bench
+    https://github.com/official-stockfish/Stockfish/blob/master/src/benchmark.cpp
+*/
+const SEARCH_BENCH_POSITIONS: [&str; 14] = [
+    board::DEFAULT_FEN_STRING,
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
+    "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
+    "r3qbrk/6p1/2b2pPp/p3pP1Q/PpPpP2P/3P1B2/2PB3K/R5R1 w - - 16 42",
+    "6k1/1R3p2/6p1/2Bp3p/3P2q1/P7/1P2rQ1K/5R2 b - - 4 44",
+    "8/8/1p2k1p1/3p3p/1p1P1P1P/1P2PK2/8/8 w - - 3 54",
+    "7r/2p3k1/1p1p1qp1/1P1Bp3/p1P2r1P/P7/4R3/Q4RK1 w - - 0 36",
+    "r1bq1rk1/pp2b1pp/n1pp1n2/3P1p2/2P1p3/2N1P2N/PP2BPPP/R1BQ1RK1 b - - 2 10",
+    "3r3k/2r4p/1p1b3q/p4P2/P2Pp3/1B2P3/3BQ1RP/6K1 w - - 3 87",
+];
+
 fn main() {
     let matches = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
@@ -58,6 +73,53 @@ fn main() {
                 "Evaluates <FEN STRING> to benchmark move generation - incompatible with play self",
             ),
         )
+        .arg(
+            Arg::with_name("search bench")
+                .long("search-bench")
+                .help("Search a fixed set of positions to <DEPTH> and report the nodes and time taken, used to tune the search"),
+        )
+        .arg(
+            Arg::with_name("perft")
+                .long("perft")
+                .value_name("DEPTH")
+                .help("Count the leaf nodes below each move from <FEN STRING> to this depth (perft divide)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("perft suite")
+                .long("perft-suite")
+                .value_name("EPD FILE")
+                .help("Check move generation against the perft counts in an EPD file, use \"bundled\" for the built in suite")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pgn")
+                .long("pgn")
+                .value_name("PGN FILE")
+                .help("Replay the games in a PGN file and print the final position of each as a FEN string")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ply")
+                .long("ply")
+                .value_name("PLY")
+                .requires("pgn")
+                .help("Print the position after this many plies of each game in the PGN instead")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pgn out")
+                .long("pgn-out")
+                .value_name("PGN FILE")
+                .help("Save the game played with play self to this file as a PGN")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pgn eval")
+                .long("pgn-eval")
+                .requires("pgn out")
+                .help("Add the search score of each move to the saved PGN as {[%eval ...]} comments"),
+        )
         .arg(
             Arg::with_name("simple print")
                 .short("S")
@@ -81,7 +143,7 @@ fn main() {
     }
 
     let fen = matches.value_of("fen").unwrap_or(board::DEFAULT_FEN_STRING);
-    let board = match board::BoardState::from_fen(fen) {
+    let mut board = match board::BoardState::from_fen(fen) {
         Ok(b) => b,
         Err(err) => {
             println!("{}", err);
@@ -89,12 +151,55 @@ fn main() {
         }
     };
 
+    if let Some(epd_file) = matches.value_of("perft suite") {
+        if !run_perft_suite(epd_file) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(pgn_file) = matches.value_of("pgn") {
+        let ply = match matches.value_of("ply").map(str::parse::<usize>) {
+            Some(Ok(ply)) => Some(ply),
+            Some(Err(_)) => {
+                println!("Invalid ply provided");
+                return;
+            }
+            None => None,
+        };
+        if !replay_pgn(pgn_file, ply) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(perft_depth) = matches.value_of("perft") {
+        let perft_depth = match perft_depth.parse::<usize>() {
+            Ok(d) if d > 0 => d,
+            _ => {
+                println!("Invalid perft depth provided");
+                return;
+            }
+        };
+        let zobrist_hasher = zobrist::ZobristHasher::create_zobrist_hasher();
+        let divide = move_generation::perft_divide(&mut board, perft_depth, &zobrist_hasher);
+        for (mov, nodes) in &divide {
+            println!("{}: {}", mov, nodes);
+        }
+        println!();
+        println!(
+            "Nodes searched: {}",
+            divide.iter().map(|(_, nodes)| nodes).sum::<u64>()
+        );
+        return;
+    }
+
     if matches.is_present("test bench") {
         let mut moves_states = [0; search::MAX_DEPTH as usize];
         let start = Instant::now();
         let zobrist_hasher = zobrist::ZobristHasher::create_zobrist_hasher();
         move_generation::generate_moves_test(
-            &board,
+            &mut board,
             0,
             depth as usize,
             &mut moves_states,
@@ -102,24 +207,171 @@ fn main() {
             &zobrist_hasher,
         );
         let time_to_run = Instant::now().duration_since(start);
-        let nodes: u32 = moves_states.iter().sum();
+        let nodes: u64 = moves_states.iter().sum();
         println!(
             "Searched to a depth of {} and evaluated {} nodes in {:?} for a total speed of {} nps",
             depth,
             nodes,
             time_to_run,
-            nodes / max(time_to_run.as_secs() as u32, 1)
+            nodes / max(time_to_run.as_secs(), 1)
         );
         return;
     }
 
+    if matches.is_present("search bench") {
+        run_search_bench(depth);
+        return;
+    }
+
     if matches.is_present("play self") {
         let simple_print = matches.is_present("simple print");
         let max_moves = 100;
         let time_per_move_ms = 1000;
-        engine::play_game_against_self(&board, max_moves, time_per_move_ms, simple_print);
+        let game = engine::play_game_against_self(
+            &board,
+            max_moves,
+            time_per_move_ms,
+            simple_print,
+            Some(uci::uci_reporter()),
+        );
+        if let Some(pgn_file) = matches.value_of("pgn out") {
+            let pgn = game.to_pgn(matches.is_present("pgn eval"));
+            if let Err(err) = fs::write(pgn_file, pgn) {
+                println!("Could not write {}: {}", pgn_file, err);
+                process::exit(1);
+            }
+        }
         return;
     }
 
     uci::play_game_uci();
 }
+
+/*
+    Run every position in a perft EPD file, printing the divide for any position that does
+    not match, returns true if every position matched
+*/
+fn run_perft_suite(epd_file: &str) -> bool {
+    let epd = match epd_file {
+        "bundled" => perft_suite::BUNDLED_SUITE.to_string(),
+        _ => match fs::read_to_string(epd_file) {
+            Ok(epd) => epd,
+            Err(err) => {
+                println!("Could not read {}: {}", epd_file, err);
+                return false;
+            }
+        },
+    };
+    let suite = match perft_suite::parse_epd(&epd) {
+        Ok(suite) => suite,
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+
+    let start = Instant::now();
+    let zobrist_hasher = zobrist::ZobristHasher::create_zobrist_hasher();
+    let mut nodes = 0;
+    let mut failed = 0;
+    for entry in &suite {
+        match perft_suite::check_perft_entry(entry, None, &zobrist_hasher) {
+            Ok(entry_nodes) => {
+                nodes += entry_nodes;
+                println!("ok {}", entry.fen);
+            }
+            Err(mismatch) => {
+                failed += 1;
+                println!("FAILED {}", mismatch);
+            }
+        }
+    }
+    println!(
+        "{} of {} positions passed, {} nodes in {:?}",
+        suite.len() - failed,
+        suite.len(),
+        nodes,
+        Instant::now().duration_since(start)
+    );
+    failed == 0
+}
+
+/*
+    Search every position in the search benchmark from an empty transposition table,
+    printing the best move and nodes searched for each and the totals at the end
+*/
+fn run_search_bench(depth: u8) {
+    let start = Instant::now();
+    let mut nodes = 0;
+    for fen in SEARCH_BENCH_POSITIONS {
+        let mut engine = engine::Engine::new();
+        engine.set_position(fen, &[]).unwrap();
+        let position_start = Instant::now();
+        let result = engine
+            .search(search::SearchLimits {
+                depth: Some(depth),
+                ..Default::default()
+            })
+            .unwrap();
+        nodes += result.nodes;
+        println!(
+            "bestmove {} nodes {} time {:?} {}",
+            result.best_move,
+            result.nodes,
+            Instant::now().duration_since(position_start),
+            fen
+        );
+    }
+    let time_to_run = Instant::now().duration_since(start);
+    println!(
+        "Searched {} positions to a depth of {}, {} nodes in {:?} for a total speed of {} nps",
+        SEARCH_BENCH_POSITIONS.len(),
+        depth,
+        nodes,
+        time_to_run,
+        nodes as u128 * 1000 / max(time_to_run.as_millis(), 1)
+    );
+}
+
+/*
+    Replay every game in a PGN file and print the FEN of its final position, or
+    of the position after the given number of plies
+
+    Returns false if the file could not be read or a game could not be replayed
+*/
+fn replay_pgn(pgn_file: &str, ply: Option<usize>) -> bool {
+    let games = match fs::read_to_string(pgn_file) {
+        Ok(pgn) => pgn::parse_pgn(&pgn),
+        Err(err) => {
+            println!("Could not read {}: {}", pgn_file, err);
+            return false;
+        }
+    };
+    let games = match games {
+        Ok(games) => games,
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+    };
+
+    let mut replayed = true;
+    for (i, game) in games.iter().enumerate() {
+        let positions = match game.positions() {
+            Ok(positions) => positions,
+            Err(err) => {
+                println!("Game {}: {}", i + 1, err);
+                replayed = false;
+                continue;
+            }
+        };
+        match ply.map_or(positions.last(), |ply| positions.get(ply)) {
+            Some(board) => println!("{}", board.to_fen()),
+            None => {
+                println!("Game {} only has {} plies", i + 1, positions.len() - 1);
+                replayed = false;
+            }
+        }
+    }
+    replayed
+}
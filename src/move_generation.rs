@@ -1,18 +1,8 @@
+use crate::bitboard::{self, Bitboard};
 pub use crate::board::*;
-pub use crate::board::{PieceColor::*, PieceKind::*};
 pub use crate::evaluation::*;
 use crate::zobrist::ZobristHasher;
-
-const KNIGHT_CORDS: [(i8, i8); 8] = [
-    (1, 2),
-    (1, -2),
-    (2, 1),
-    (2, -1),
-    (-1, 2),
-    (-1, -2),
-    (-2, -1),
-    (-2, 1),
-];
+use std::cmp::max;
 
 // MVV-LVA score, see https://www.chessprogramming.org/MVV-LVA
 // addressed as [victim][attacker]
@@ -42,43 +32,31 @@ pub enum MoveGenerationMode {
     CapturesOnly,
 }
 
-const WHITE_KING_SIDE_CASTLE_ALG: Option<(Point, Point)> = Some((Point(9, 6), Point(9, 8)));
-const WHITE_QUEEN_SIDE_CASTLE_ALG: Option<(Point, Point)> = Some((Point(9, 6), Point(9, 4)));
-const BLACK_KING_SIDE_CASTLE_ALG: Option<(Point, Point)> = Some((Point(2, 6), Point(2, 8)));
-const BLACK_QUEEN_SIDE_CASTLE_ALG: Option<(Point, Point)> = Some((Point(2, 6), Point(2, 4)));
+const WHITE_KING_SIDE_CASTLE: Move = Move::new(Point(9, 6), Point(9, 8), None, Move::CASTLE);
+const WHITE_QUEEN_SIDE_CASTLE: Move = Move::new(Point(9, 6), Point(9, 4), None, Move::CASTLE);
+const BLACK_KING_SIDE_CASTLE: Move = Move::new(Point(2, 6), Point(2, 8), None, Move::CASTLE);
+const BLACK_QUEEN_SIDE_CASTLE: Move = Move::new(Point(2, 6), Point(2, 4), None, Move::CASTLE);
 
 /*
     Generate all possible *legal* moves from the given board
-    Also sets appropriate variables for the board state
 */
-pub fn generate_moves(
-    board: &BoardState,
-    move_gen_mode: MoveGenerationMode,
-    zobrist_hasher: &ZobristHasher,
-) -> Vec<BoardState> {
+pub fn generate_moves(board: &BoardState, move_gen_mode: MoveGenerationMode) -> Vec<Move> {
     //usually there is at minimum 16 moves in a position, so it make sense to preallocate some space to avoid excessive reallocations
-    let mut new_moves: Vec<BoardState> = Vec::with_capacity(16);
-
-    for i in BOARD_START..BOARD_END {
-        for j in BOARD_START..BOARD_END {
-            if let Square::Full(piece) = board.board[i][j] {
-                if piece.color == board.to_move {
-                    generate_moves_for_piece(
-                        piece,
-                        board,
-                        Point(i, j),
-                        &mut new_moves,
-                        move_gen_mode,
-                        zobrist_hasher,
-                    );
-                }
-            }
+    let mut new_moves: Vec<Move> = Vec::with_capacity(32);
+
+    for square in bitboard::squares(board.occupied[board.to_move.index()]) {
+        let square_cords = bitboard::point(square);
+        if let Square::Full(piece) = board.board[square_cords.0][square_cords.1] {
+            generate_moves_for_piece(piece, board, square_cords, &mut new_moves, move_gen_mode);
         }
     }
 
     if move_gen_mode == MoveGenerationMode::AllMoves {
-        generate_castling_moves(board, &mut new_moves, zobrist_hasher);
+        generate_castling_moves(board, &mut new_moves);
     }
+
+    // if you make your move, and you are in check, this move is not valid
+    new_moves.retain(|&mov| is_legal(board, mov));
     new_moves
 }
 
@@ -92,6 +70,21 @@ pub fn is_check(board: &BoardState, color: PieceColor) -> bool {
     }
 }
 
+/*
+    The squares a piece of the given color may move to, all empty or enemy squares
+    when generating every move or only the enemy squares when generating captures
+*/
+fn target_squares(
+    board: &BoardState,
+    color: PieceColor,
+    move_generation_mode: MoveGenerationMode,
+) -> Bitboard {
+    match move_generation_mode {
+        MoveGenerationMode::AllMoves => !board.occupied[color.index()],
+        MoveGenerationMode::CapturesOnly => board.occupied[color.opposite().index()],
+    }
+}
+
 /*
     Generate pseudo-legal moves for a knight
 */
@@ -100,24 +93,10 @@ fn knight_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    for (r, c) in &KNIGHT_CORDS {
-        let row = (row as i8 + r) as usize;
-        let col = (col as i8 + c) as usize;
-        let square = board.board[row][col];
-
-        if square.is_empty_or_color(piece.color.opposite()) {
-            if move_generation_mode == MoveGenerationMode::CapturesOnly {
-                if !square.is_empty() {
-                    moves.push(Point(row, col));
-                }
-            } else {
-                moves.push(Point(row, col));
-            }
-        }
-    }
+) -> Bitboard {
+    let square = bitboard::square(Point(row, col));
+    bitboard::KNIGHT_ATTACKS[square] & target_squares(board, piece.color, move_generation_mode)
 }
 
 /*
@@ -128,57 +107,39 @@ fn pawn_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    match piece.color {
-        // white pawns move up board
-        White => {
-            // check capture
-            let left_cap = board.board[row - 1][col - 1];
-            let right_cap = board.board[row - 1][col + 1];
-            if let Square::Full(Piece { color: Black, .. }) = left_cap {
-                moves.push(Point(row - 1, col - 1));
-            }
-            if let Square::Full(Piece { color: Black, .. }) = right_cap {
-                moves.push(Point(row - 1, col + 1));
-            }
+) -> Bitboard {
+    let pawn = bitboard::bit(Point(row, col));
+    let enemies = board.occupied[piece.color.opposite().index()];
+    let empty = !board.all_occupied();
+
+    // check captures
+    let mut moves =
+        bitboard::pawn_attacks(piece.color, bitboard::square(Point(row, col))) & enemies;
 
-            // check a normal push
-            if move_generation_mode == MoveGenerationMode::AllMoves
-                && (board.board[row - 1][col]).is_empty()
-            {
-                moves.push(Point(row - 1, col));
+    if move_generation_mode == MoveGenerationMode::AllMoves {
+        match piece.color {
+            // white pawns move up board
+            White => {
+                let push = (pawn >> 8) & empty;
+                moves |= push;
                 // check double push
-                if row == 8 && (board.board[row - 2][col]).is_empty() {
-                    moves.push(Point(row - 2, col));
+                if row == 8 {
+                    moves |= (push >> 8) & empty;
                 }
             }
-        }
-        // black pawns move down board
-        Black => {
-            // check capture
-            let left_cap = board.board[row + 1][col + 1];
-            let right_cap = board.board[row + 1][col - 1];
-            if let Square::Full(Piece { color: White, .. }) = left_cap {
-                moves.push(Point(row + 1, col + 1));
-            }
-            if let Square::Full(Piece { color: White, .. }) = right_cap {
-                moves.push(Point(row + 1, col - 1));
-            }
-
-            // check a normal push
-            if move_generation_mode == MoveGenerationMode::AllMoves
-                && (board.board[row + 1][col]).is_empty()
-            {
-                moves.push(Point(row + 1, col));
+            // black pawns move down board
+            Black => {
+                let push = (pawn << 8) & empty;
+                moves |= push;
                 // check double push
-                if row == 3 && (board.board[row + 2][col]).is_empty() {
-                    moves.push(Point(row + 2, col));
+                if row == 3 {
+                    moves |= (push << 8) & empty;
                 }
             }
         }
     }
+    moves
 }
 
 /*
@@ -195,30 +156,17 @@ fn pawn_moves_en_passant(
     col: usize,
     board: &BoardState,
 ) -> Option<Point> {
-    if let Some(double_moved_pawn) = board.pawn_double_move {
-        let left_cap;
-        let right_cap;
-
-        match piece.color {
-            White if row == BOARD_START + 3 => {
-                left_cap = Point(row - 1, col - 1);
-                right_cap = Point(row - 1, col + 1);
-            }
-            Black if row == BOARD_START + 4 => {
-                left_cap = Point(row + 1, col + 1);
-                right_cap = Point(row + 1, col - 1);
-            }
-            _ => return None,
-        }
-
-        if left_cap == double_moved_pawn {
-            return Some(left_cap);
-        } else if right_cap == double_moved_pawn {
-            return Some(right_cap);
-        }
+    let double_moved_pawn = board.pawn_double_move?;
+    let on_en_passant_row = match piece.color {
+        White => row == BOARD_START + 3,
+        Black => row == BOARD_START + 4,
+    };
+    let attacks = bitboard::pawn_attacks(piece.color, bitboard::square(Point(row, col)));
+    if on_en_passant_row && attacks & bitboard::bit(double_moved_pawn) != 0 {
+        Some(double_moved_pawn)
+    } else {
+        None
     }
-
-    None
 }
 
 /*
@@ -229,26 +177,10 @@ fn king_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    for i in 0..3 {
-        let row = row + i - 1;
-        for j in 0..3 {
-            let col = col + j - 1;
-            let square = board.board[row][col];
-
-            if square.is_empty_or_color(piece.color.opposite()) {
-                if move_generation_mode == MoveGenerationMode::CapturesOnly {
-                    if !square.is_empty() {
-                        moves.push(Point(row, col));
-                    }
-                } else {
-                    moves.push(Point(row, col));
-                }
-            }
-        }
-    }
+) -> Bitboard {
+    let square = bitboard::square(Point(row, col));
+    bitboard::KING_ATTACKS[square] & target_squares(board, piece.color, move_generation_mode)
 }
 
 /*
@@ -259,26 +191,11 @@ fn rook_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    for (r, c) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
-        let mut row = row as i8 + r;
-        let mut col = col as i8 + c;
-        let mut square = board.board[row as usize][col as usize];
-        while square.is_empty() {
-            if move_generation_mode == MoveGenerationMode::AllMoves {
-                moves.push(Point(row as usize, col as usize));
-            }
-            row += r;
-            col += c;
-            square = board.board[row as usize][col as usize];
-        }
-
-        if square.is_color(piece.color.opposite()) {
-            moves.push(Point(row as usize, col as usize));
-        }
-    }
+) -> Bitboard {
+    let square = bitboard::square(Point(row, col));
+    bitboard::rook_attacks(square, board.all_occupied())
+        & target_squares(board, piece.color, move_generation_mode)
 }
 
 /*
@@ -289,26 +206,11 @@ fn bishop_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    for (r, c) in &[(1, -1), (1, 1), (-1, 1), (-1, -1)] {
-        let mut row = row as i8 + r;
-        let mut col = col as i8 + c;
-        let mut square = board.board[row as usize][col as usize];
-        while square.is_empty() {
-            if move_generation_mode == MoveGenerationMode::AllMoves {
-                moves.push(Point(row as usize, col as usize));
-            }
-            row += r;
-            col += c;
-            square = board.board[row as usize][col as usize];
-        }
-
-        if square.is_color(piece.color.opposite()) {
-            moves.push(Point(row as usize, col as usize));
-        }
-    }
+) -> Bitboard {
+    let square = bitboard::square(Point(row, col));
+    bitboard::bishop_attacks(square, board.all_occupied())
+        & target_squares(board, piece.color, move_generation_mode)
 }
 
 /*
@@ -319,11 +221,11 @@ fn queen_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
-    rook_moves(piece, row, col, board, moves, move_generation_mode);
-    bishop_moves(piece, row, col, board, moves, move_generation_mode);
+) -> Bitboard {
+    let square = bitboard::square(Point(row, col));
+    bitboard::queen_attacks(square, board.all_occupied())
+        & target_squares(board, piece.color, move_generation_mode)
 }
 
 /*
@@ -335,93 +237,88 @@ fn get_moves(
     row: usize,
     col: usize,
     board: &BoardState,
-    moves: &mut Vec<Point>,
     move_generation_mode: MoveGenerationMode,
-) {
+) -> Bitboard {
     match piece.kind {
-        Pawn => pawn_moves(piece, row, col, board, moves, move_generation_mode),
-        Rook => rook_moves(piece, row, col, board, moves, move_generation_mode),
-        Bishop => bishop_moves(piece, row, col, board, moves, move_generation_mode),
-        Knight => knight_moves(piece, row, col, board, moves, move_generation_mode),
-        King => king_moves(piece, row, col, board, moves, move_generation_mode),
-        Queen => queen_moves(piece, row, col, board, moves, move_generation_mode),
+        Pawn => pawn_moves(piece, row, col, board, move_generation_mode),
+        Rook => rook_moves(piece, row, col, board, move_generation_mode),
+        Bishop => bishop_moves(piece, row, col, board, move_generation_mode),
+        Knight => knight_moves(piece, row, col, board, move_generation_mode),
+        King => king_moves(piece, row, col, board, move_generation_mode),
+        Queen => queen_moves(piece, row, col, board, move_generation_mode),
     }
 }
 
 /*
-    Determine if the given position is check
+    Determine if a pseudo-legal move leaves the king of the player making it safe
 
-    Rather than checking each piece to see if it attacks the king
-    this function checks all possible attack squares to the king and
-    sees if the piece is there, thus it is important the king_location is set
+    Rather than making the move, work out which squares would be occupied afterwards
+    and look for attacks on the king with the captured piece taken off the board
 */
-fn is_check_cords(board: &BoardState, color: PieceColor, square_cords: Point) -> bool {
-    let attacking_color = color.opposite();
-    let attacking_rook = Piece::rook(attacking_color);
-    let attacking_queen = Piece::queen(attacking_color);
-    let attacking_bishop = Piece::bishop(attacking_color);
-    let attacking_knight = Piece::knight(attacking_color);
-    let attacking_pawn = Piece::pawn(attacking_color);
-
-    // Check from rook or queen
-    for (r, c) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
-        let mut row = square_cords.0 as i8 + r;
-        let mut col = square_cords.1 as i8 + c;
-        let mut square = board.board[row as usize][col as usize];
-        while square.is_empty() {
-            row += r;
-            col += c;
-            square = board.board[row as usize][col as usize];
-        }
-
-        if square == attacking_rook || square == attacking_queen {
-            return true;
-        }
-    }
-
-    // Check from bishop or queen
-    for (r, c) in &[(1, -1), (1, 1), (-1, 1), (-1, -1)] {
-        let mut row = square_cords.0 as i8 + r;
-        let mut col = square_cords.1 as i8 + c;
-        let mut square = board.board[row as usize][col as usize];
-        while square.is_empty() {
-            row += r;
-            col += c;
-            square = board.board[row as usize][col as usize];
-        }
-
-        if square == attacking_bishop || square == attacking_queen {
-            return true;
-        }
-    }
-
-    // Check from knight
-    for (r, c) in &KNIGHT_CORDS {
-        let row = (square_cords.0 as i8 + r) as usize;
-        let col = (square_cords.1 as i8 + c) as usize;
-        let square = board.board[row][col];
-
-        if square == attacking_knight {
-            return true;
-        }
-    }
-
-    // Check from pawn
-    let pawn_row = match color {
-        White => square_cords.0 - 1,
-        Black => square_cords.0 + 1,
+fn is_legal(board: &BoardState, mov: Move) -> bool {
+    let color = board.to_move;
+    let (start, end) = (mov.from(), mov.to());
+    let mut captured = bitboard::bit(end);
+    if mov.is_en_passant() {
+        // the captured pawn is beside the moving pawn, not on the target square
+        captured |= bitboard::bit(Point(start.0, end.1));
+    }
+    let occupied = (board.all_occupied() & !bitboard::bit(start) & !captured) | bitboard::bit(end);
+    let king_location = match color {
+        White => board.white_king_location,
+        Black => board.black_king_location,
+    };
+    let king_location = if start == king_location {
+        end
+    } else {
+        king_location
     };
+    !is_attacked(
+        board,
+        color,
+        bitboard::square(king_location),
+        occupied,
+        captured,
+    )
+}
 
-    if board.board[pawn_row][square_cords.1 - 1] == attacking_pawn
-        || board.board[pawn_row][square_cords.1 + 1] == attacking_pawn
-    {
-        return true;
-    }
+/*
+    Determine if the given square is attacked by the opponent of color
+
+    Rather than checking each piece to see if it attacks the square
+    this function looks outwards from the square with each kind of piece
+    and sees if an enemy piece of that kind is there
+*/
+fn is_check_cords(board: &BoardState, color: PieceColor, square_cords: Point) -> bool {
+    is_attacked(
+        board,
+        color,
+        bitboard::square(square_cords),
+        board.all_occupied(),
+        bitboard::EMPTY,
+    )
+}
 
-    // Check from king
-    // By using the king location here we can just check if they are within one square of each other
-    (board.black_king_location.0 as i8 - board.white_king_location.0 as i8).abs() <= 1
-        && (board.black_king_location.1 as i8 - board.white_king_location.1 as i8).abs() <= 1
+/*
+    Determine if a square is attacked by the opponent of color given which squares are
+    occupied, any enemy pieces in captured are ignored
+*/
+fn is_attacked(
+    board: &BoardState,
+    color: PieceColor,
+    square: usize,
+    occupied: Bitboard,
+    captured: Bitboard,
+) -> bool {
+    let attackers = board.pieces[color.opposite().index()].map(|pieces| pieces & !captured);
+    let rooks_and_queens = attackers[Rook.index()] | attackers[Queen.index()];
+    let bishops_and_queens = attackers[Bishop.index()] | attackers[Queen.index()];
+
+    bitboard::pawn_attacks(color, square) & attackers[Pawn.index()] != 0
+        || bitboard::KNIGHT_ATTACKS[square] & attackers[Knight.index()] != 0
+        || bitboard::KING_ATTACKS[square] & attackers[King.index()] != 0
+        || bitboard::rook_attacks(square, occupied) & rooks_and_queens != 0
+        || bitboard::bishop_attacks(square, occupied) & bishops_and_queens != 0
 }
 
 /*
@@ -559,146 +456,49 @@ fn generate_moves_for_piece(
     piece: Piece,
     board: &BoardState,
     square_cords: Point,
-    new_moves: &mut Vec<BoardState>,
+    new_moves: &mut Vec<Move>,
     move_generation_mode: MoveGenerationMode,
-    zobrist_hasher: &ZobristHasher,
 ) {
-    let mut moves: Vec<Point> = Vec::new();
-    let Piece { color, kind } = piece;
-    get_moves(
+    let kind = piece.kind;
+    let moves = get_moves(
         piece,
         square_cords.0,
         square_cords.1,
         board,
-        &mut moves,
         move_generation_mode,
     );
+    let enemies = board.occupied[piece.color.opposite().index()];
 
-    // make all the valid moves of this piece
-    for mov in moves {
-        let mut new_board = board.clone();
-        new_board.pawn_promotion = None;
-        new_board.swap_color(zobrist_hasher);
-
-        // update king location if we are moving the king
-        if kind == King {
-            match color {
-                White => new_board.white_king_location = mov,
-                Black => new_board.black_king_location = mov,
-            }
-        }
-
-        let target_square = new_board.board[mov.0][mov.1];
-        if let Square::Full(target_piece) = target_square {
-            new_board.order_heuristic = MVV_LVA[target_piece.index()][piece.index()];
+    for target in bitboard::squares(moves) {
+        let mov = bitboard::point(target);
+        let flags = if enemies & (1 << target) == 0 {
+            Move::QUIET
         } else {
-            // by default all moves are given a neutral score
-            new_board.order_heuristic = 0;
-        }
-
-        // move the piece, this will take care of any captures as well, excluding en passant
-        new_board.move_piece(square_cords, mov, zobrist_hasher);
-        new_board.last_move = Some((square_cords, mov));
-
-        // if you make your move, and you are in check, this move is not valid
-        if is_check(&new_board, color) {
-            continue;
-        }
-
-        // if the rook or king move, take away castling privileges
-        if kind == King {
-            if color == White {
-                new_board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-                new_board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-            } else {
-                new_board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-                new_board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-            }
-        } else if square_cords.0 == BOARD_END - 1 && square_cords.1 == BOARD_END - 1 {
-            new_board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-        } else if square_cords.0 == BOARD_END - 1 && square_cords.1 == BOARD_START {
-            new_board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-        } else if square_cords.0 == BOARD_START && square_cords.1 == BOARD_START {
-            new_board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-        } else if square_cords.0 == BOARD_START && square_cords.1 == BOARD_END - 1 {
-            new_board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-        }
-
-        // if the rook is captured, take away castling privileges
-        if mov.0 == BOARD_END - 1 && mov.1 == BOARD_END - 1 {
-            new_board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-        } else if mov.0 == BOARD_END - 1 && mov.1 == BOARD_START {
-            new_board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-        } else if mov.0 == BOARD_START && mov.1 == BOARD_START {
-            new_board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-        } else if mov.0 == BOARD_START && mov.1 == BOARD_END - 1 {
-            new_board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-        }
-
-        // checks if the pawn has moved two spaces, if it has it can be captured en passant, record the space *behind* the pawn ie the valid capture square
-        if move_generation_mode == MoveGenerationMode::AllMoves {
-            if kind == Pawn && (square_cords.0 as i8 - mov.0 as i8).abs() == 2 {
-                let en_passant_square = match color {
-                    White => Point(mov.0 + 1, mov.1),
-                    Black => Point(mov.0 - 1, mov.1),
-                };
+            Move::CAPTURE
+        };
 
-                new_board.pawn_double_move = Some(en_passant_square);
-                new_board.zobrist_key ^= zobrist_hasher.get_val_for_en_passant(en_passant_square.1);
-            } else {
-                // the most recent move was not a double pawn move, unset any possibly existing pawn double move
-                new_board.unset_pawn_double_move(zobrist_hasher);
-            }
+        if kind == Pawn && (mov.0 == BOARD_START || mov.0 == BOARD_END - 1) {
             // deal with pawn promotions
-            if mov.0 == BOARD_START && color == White && kind == Pawn {
-                promote_pawn(
-                    &new_board,
-                    White,
-                    square_cords,
-                    mov,
-                    new_moves,
-                    zobrist_hasher,
-                );
-            } else if mov.0 == BOARD_END - 1 && color == Black && kind == Pawn {
-                promote_pawn(
-                    &new_board,
-                    Black,
-                    square_cords,
-                    mov,
-                    new_moves,
-                    zobrist_hasher,
-                );
-            } else {
-                new_moves.push(new_board);
+            for promotion in [Queen, Knight, Bishop, Rook] {
+                new_moves.push(Move::new(square_cords, mov, Some(promotion), flags));
             }
+        } else if kind == Pawn && (square_cords.0 as i8 - mov.0 as i8).abs() == 2 {
+            // the pawn has moved two spaces, it can be captured en passant
+            new_moves.push(Move::new(square_cords, mov, None, Move::DOUBLE_PAWN_PUSH));
         } else {
-            new_moves.push(new_board);
+            new_moves.push(Move::new(square_cords, mov, None, flags));
         }
     }
 
     // take care of en passant captures
-    if board.pawn_double_move.is_some() && kind == Pawn {
-        let en_passant = pawn_moves_en_passant(piece, square_cords.0, square_cords.1, board);
-        if let Some(mov) = en_passant {
-            let mut new_board = board.clone();
-            new_board.last_move = Some((square_cords, mov));
-            new_board.swap_color(zobrist_hasher);
-            new_board.unset_pawn_double_move(zobrist_hasher);
-            new_board.move_piece(square_cords, mov, zobrist_hasher);
-            if color == White {
-                new_board.board[mov.0 + 1][mov.1] = Square::Empty;
-                new_board.zobrist_key ^=
-                    zobrist_hasher.get_val_for_piece(Piece::pawn(Black), Point(mov.0 + 1, mov.1));
-            } else {
-                new_board.board[mov.0 - 1][mov.1] = Square::Empty;
-                new_board.zobrist_key ^=
-                    zobrist_hasher.get_val_for_piece(Piece::pawn(White), Point(mov.0 - 1, mov.1));
-            }
-
-            // if you make a move, and you do not end up in check, then this move is valid
-            if !is_check(&new_board, board.to_move) {
-                new_moves.push(new_board);
-            }
+    if kind == Pawn {
+        if let Some(mov) = pawn_moves_en_passant(piece, square_cords.0, square_cords.1, board) {
+            new_moves.push(Move::new(
+                square_cords,
+                mov,
+                None,
+                Move::CAPTURE | Move::EN_PASSANT,
+            ));
         }
     }
 }
@@ -706,142 +506,141 @@ fn generate_moves_for_piece(
 /*
     Given the current board, attempt to castle
     If castling is possible add the move the the list of possible moves
-    Will also update appropriate castling variables if castling was successful
 */
-fn generate_castling_moves(
-    board: &BoardState,
-    new_moves: &mut Vec<BoardState>,
-    zobrist_hasher: &ZobristHasher,
-) {
-    if board.to_move == White && can_castle(board, &CastlingType::WhiteKingSide) {
-        let mut new_board = board.clone();
-        new_board.swap_color(zobrist_hasher);
-        new_board.unset_pawn_double_move(zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-        new_board.white_king_location = Point(BOARD_END - 1, BOARD_END - 2);
-        new_board.last_move = WHITE_KING_SIDE_CASTLE_ALG;
-
-        // move king and rook
-        new_board.move_piece(
-            board.white_king_location,
-            new_board.white_king_location,
-            zobrist_hasher,
-        );
-        new_board.move_piece(
-            Point(BOARD_END - 1, BOARD_END - 1),
-            Point(BOARD_END - 1, BOARD_END - 3),
-            zobrist_hasher,
-        );
-        new_moves.push(new_board);
-    }
-
-    if board.to_move == White && can_castle(board, &CastlingType::WhiteQueenSide) {
-        let mut new_board = board.clone();
-        new_board.swap_color(zobrist_hasher);
-        new_board.unset_pawn_double_move(zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-        new_board.white_king_location = Point(BOARD_END - 1, BOARD_START + 2);
-        new_board.last_move = WHITE_QUEEN_SIDE_CASTLE_ALG;
-
-        // move king and rook
-        new_board.move_piece(
-            board.white_king_location,
-            new_board.white_king_location,
-            zobrist_hasher,
-        );
-        new_board.move_piece(
-            Point(BOARD_END - 1, BOARD_START),
-            Point(BOARD_END - 1, BOARD_START + 3),
-            zobrist_hasher,
-        );
-        new_moves.push(new_board);
-    }
-
-    if board.to_move == Black && can_castle(board, &CastlingType::BlackKingSide) {
-        let mut new_board = board.clone();
-        new_board.swap_color(zobrist_hasher);
-        new_board.unset_pawn_double_move(zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-        new_board.black_king_location = Point(BOARD_START, BOARD_END - 2);
-        new_board.last_move = BLACK_KING_SIDE_CASTLE_ALG;
-        // move king and rook
-        new_board.move_piece(
-            board.black_king_location,
-            new_board.black_king_location,
-            zobrist_hasher,
-        );
-        new_board.move_piece(
-            Point(BOARD_START, BOARD_END - 1),
-            Point(BOARD_START, BOARD_END - 3),
-            zobrist_hasher,
-        );
-
-        new_moves.push(new_board);
+fn generate_castling_moves(board: &BoardState, new_moves: &mut Vec<Move>) {
+    let castling_moves = [
+        (White, CastlingType::WhiteKingSide, WHITE_KING_SIDE_CASTLE),
+        (White, CastlingType::WhiteQueenSide, WHITE_QUEEN_SIDE_CASTLE),
+        (Black, CastlingType::BlackKingSide, BLACK_KING_SIDE_CASTLE),
+        (Black, CastlingType::BlackQueenSide, BLACK_QUEEN_SIDE_CASTLE),
+    ];
+    for (color, castling_type, mov) in castling_moves {
+        if board.to_move == color && can_castle(board, &castling_type) {
+            new_moves.push(mov);
+        }
     }
+}
 
-    if board.to_move == Black && can_castle(board, &CastlingType::BlackQueenSide) {
-        let mut new_board = board.clone();
-        new_board.swap_color(zobrist_hasher);
-        new_board.unset_pawn_double_move(zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-        new_board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-        new_board.black_king_location = Point(BOARD_START, BOARD_START + 2);
-        new_board.last_move = BLACK_QUEEN_SIDE_CASTLE_ALG;
+/*
+    Score a move to help order the search, a higher value means this move will be considered first
 
-        // move king and rook
-        new_board.move_piece(
-            board.black_king_location,
-            new_board.black_king_location,
-            zobrist_hasher,
-        );
-        new_board.move_piece(
-            Point(BOARD_START, BOARD_START),
-            Point(BOARD_START, BOARD_START + 3),
-            zobrist_hasher,
-        );
+    Captures that don't lose material according to the static exchange evaluation are tried first,
+    ranked with MVV-LVA. Captures that lose material are tried after the killer moves, the ones that
+    lose the least first, and promoting to anything but a queen is tried last
+*/
+const QUEEN_PROMOTION_SCORE: i32 = 800; // queen value - pawn value
+const UNDER_PROMOTION_SCORE: i32 = -999999999; // under promotions should be tried last
+pub const GOOD_CAPTURE_SCORE: i32 = 100; // every capture that does not lose material scores at least this
+const BAD_CAPTURE_SCORE: i32 = 20; // a capture that loses material scores at most this, but always above quiet moves
+pub fn order_heuristic(board: &BoardState, mov: Move) -> i32 {
+    match mov.promotion {
+        Some(Queen) => QUEEN_PROMOTION_SCORE,
+        Some(_) => UNDER_PROMOTION_SCORE,
+        None if mov.is_capture() => {
+            let victim = match mov.is_en_passant() {
+                true => Pawn,
+                false => piece_kind_at(board, mov.to()),
+            };
+            let attacker = piece_kind_at(board, mov.from());
+            let exchange = static_exchange(board, mov);
+            if exchange >= 0 {
+                GOOD_CAPTURE_SCORE + MVV_LVA[victim.index()][attacker.index()]
+            } else {
+                (BAD_CAPTURE_SCORE + exchange / 100).max(1)
+            }
+        }
+        // by default all moves are given a neutral score
+        None => 0,
+    }
+}
 
-        new_moves.push(new_board);
+fn piece_kind_at(board: &BoardState, point: Point) -> PieceKind {
+    match board.board[point.0][point.1] {
+        Square::Full(piece) => piece.kind,
+        _ => panic!("Expected a piece at {:?}", point),
     }
 }
 
+// Rough piece values for judging exchanges, indexed by PieceKind
+const SEE_PIECE_VALUES: [i32; 6] = [20000, 900, 500, 330, 320, 100];
+// the order in which pieces are used to recapture, least valuable first
+const SEE_RECAPTURE_ORDER: [PieceKind; 6] = [Pawn, Knight, Bishop, Rook, Queen, King];
+
 /*
-    Executes a pawn promotion on the given cords
+    Static exchange evaluation, see https://www.chessprogramming.org/Static_Exchange_Evaluation
 
-    This function assumes that the board state is a valid pawn promotion and does not do additional checks
+    The material won or lost by a capture once every piece that can join in has recaptured
+    on the target square. Both sides recapture with their least valuable piece and can stop
+    whenever recapturing would lose material. Sliders lined up behind a piece that has taken
+    part join in as it leaves the line, pins are ignored
 */
-const QUEEN_PROMOTION_SCORE: i32 = 800; // queen value - pawn value
-const UNDER_PROMOTION_SCORE: i32 = -999999999; // under promotions should be tried last
-fn promote_pawn(
-    board: &BoardState,
-    color: PieceColor,
-    start: Point,
-    target: Point,
-    moves: &mut Vec<BoardState>,
-    zobrist_hasher: &ZobristHasher,
-) {
-    for kind in [Queen, Knight, Bishop, Rook] {
-        let mut new_board = board.clone();
-        new_board.unset_pawn_double_move(zobrist_hasher);
-        let promotion_piece = Piece { color, kind };
-        new_board.board[target.0][target.1] = Square::Full(promotion_piece);
-        new_board.last_move = Some((start, target));
-        new_board.pawn_promotion = Some(promotion_piece);
-
-        // promoting to a piece that isn't a queen is rarely a good idea
-        new_board.order_heuristic = if kind == Queen {
-            QUEEN_PROMOTION_SCORE
-        } else {
-            UNDER_PROMOTION_SCORE
-        };
+pub fn static_exchange(board: &BoardState, mov: Move) -> i32 {
+    let target = bitboard::square(mov.to());
+    let mut occupied = board.all_occupied() & !bitboard::bit(mov.from());
+    let mut piece_on_target = piece_kind_at(board, mov.from());
+
+    let mut gain = [0; 33];
+    gain[0] = if mov.is_en_passant() {
+        // the captured pawn is next to the moving pawn, not on the target square
+        occupied &= !bitboard::bit(Point(mov.from().0, mov.to().1));
+        SEE_PIECE_VALUES[Pawn.index()]
+    } else if let Square::Full(victim) = board.board[mov.to().0][mov.to().1] {
+        SEE_PIECE_VALUES[victim.kind.index()]
+    } else {
+        0
+    };
+    if let Some(promotion) = mov.promotion {
+        gain[0] += SEE_PIECE_VALUES[promotion.index()] - SEE_PIECE_VALUES[Pawn.index()];
+        piece_on_target = promotion;
+    }
+
+    // gain[depth] is the material won by the side that made capture depth if it gets recaptured
+    let mut side = board.to_move.opposite();
+    let mut depth = 0;
+    loop {
+        depth += 1;
+        gain[depth] = SEE_PIECE_VALUES[piece_on_target.index()] - gain[depth - 1];
+        // neither side can come out ahead by continuing
+        if max(-gain[depth - 1], gain[depth]) < 0 {
+            break;
+        }
+
+        let attackers = attackers_to(board, target, occupied) & occupied;
+        let recapture = SEE_RECAPTURE_ORDER.iter().find_map(|&kind| {
+            let pieces = attackers & board.pieces[side.index()][kind.index()];
+            (pieces != 0).then_some((kind, pieces & pieces.wrapping_neg()))
+        });
+        match recapture {
+            Some((kind, from)) => {
+                occupied &= !from;
+                piece_on_target = kind;
+                side = side.opposite();
+            }
+            None => break,
+        }
+    }
 
-        // erase pawn and add the promotion piece
-        new_board.zobrist_key ^= zobrist_hasher.get_val_for_piece(promotion_piece, target)
-            ^ zobrist_hasher.get_val_for_piece(Piece::pawn(color), target);
-        moves.push(new_board);
+    // each side picks the better of recapturing or stopping, working back from the last capture
+    while depth > 1 {
+        depth -= 1;
+        gain[depth - 1] = -max(-gain[depth - 1], gain[depth]);
     }
+    gain[0]
+}
+
+// Every piece of either color attacking a square given which squares are occupied
+fn attackers_to(board: &BoardState, square: usize, occupied: Bitboard) -> Bitboard {
+    let [white, black] = [White, Black].map(|color| board.pieces[color.index()]);
+    let all = |kind: PieceKind| white[kind.index()] | black[kind.index()];
+    let rooks_and_queens = all(Rook) | all(Queen);
+    let bishops_and_queens = all(Bishop) | all(Queen);
+
+    bitboard::pawn_attacks(Black, square) & white[Pawn.index()]
+        | bitboard::pawn_attacks(White, square) & black[Pawn.index()]
+        | bitboard::KNIGHT_ATTACKS[square] & all(Knight)
+        | bitboard::KING_ATTACKS[square] & all(King)
+        | bitboard::rook_attacks(square, occupied) & rooks_and_queens
+        | bitboard::bishop_attacks(square, occupied) & bishops_and_queens
 }
 
 /*
@@ -850,10 +649,10 @@ fn promote_pawn(
     Will generate up until cur_depth = depth
 */
 pub fn generate_moves_test(
-    board: &BoardState,
+    board: &mut BoardState,
     cur_depth: usize,
     depth: usize,
-    move_counts: &mut [u32],
+    move_counts: &mut [u64],
     should_evaluate: bool,
     zobrist_hasher: &ZobristHasher,
 ) {
@@ -865,20 +664,67 @@ pub fn generate_moves_test(
         }
         return;
     }
-    let moves = generate_moves(board, MoveGenerationMode::AllMoves, zobrist_hasher);
-    move_counts[cur_depth] += moves.len() as u32;
+    let moves = generate_moves(board, MoveGenerationMode::AllMoves);
+    move_counts[cur_depth] += moves.len() as u64;
     for mov in moves {
+        let undo = board.make_move(mov, zobrist_hasher);
         generate_moves_test(
-            &mov,
+            board,
             cur_depth + 1,
             depth,
             move_counts,
             should_evaluate,
             zobrist_hasher,
         );
+        board.unmake_move(mov, &undo);
     }
 }
 
+/*
+    Count the leaf nodes of the legal move tree to the given depth, see https://www.chessprogramming.org/Perft
+
+    The moves at the last ply are counted rather than played
+*/
+pub fn perft(board: &mut BoardState, depth: usize, zobrist_hasher: &ZobristHasher) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = generate_moves(board, MoveGenerationMode::AllMoves);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for mov in moves {
+        let undo = board.make_move(mov, zobrist_hasher);
+        nodes += perft(board, depth - 1, zobrist_hasher);
+        board.unmake_move(mov, &undo);
+    }
+    nodes
+}
+
+/*
+    Perft split up by the moves from this position, handy to track down which move
+    a move generation bug is hiding behind
+*/
+pub fn perft_divide(
+    board: &mut BoardState,
+    depth: usize,
+    zobrist_hasher: &ZobristHasher,
+) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    generate_moves(board, MoveGenerationMode::AllMoves)
+        .into_iter()
+        .map(|mov| {
+            let undo = board.make_move(mov, zobrist_hasher);
+            let nodes = perft(board, depth - 1, zobrist_hasher);
+            board.unmake_move(mov, &undo);
+            (mov, nodes)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1020,45 +866,21 @@ mod tests {
     #[test]
     fn knight_moves_empty_board() {
         let b = BoardState::from_fen("8/8/8/8/3N4/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        knight_moves(
-            Piece::knight(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 8);
+        let ret = knight_moves(Piece::knight(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 8);
     }
 
     #[test]
     fn knight_moves_corner() {
         let b = BoardState::from_fen("N7/8/8/8/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        knight_moves(
-            Piece::knight(White),
-            2,
-            2,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 2);
+        let ret = knight_moves(Piece::knight(White), 2, 2, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 2);
     }
     #[test]
     fn knight_moves_with_other_pieces_with_capture() {
         let b = BoardState::from_fen("8/8/5n2/3NQ3/2K2P2/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        knight_moves(
-            Piece::knight(White),
-            5,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 7);
+        let ret = knight_moves(Piece::knight(White), 5, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 7);
     }
 
     // Pawn tests - white pawn
@@ -1066,106 +888,50 @@ mod tests {
     #[test]
     fn white_pawn_double_push() {
         let b = BoardState::from_fen("8/8/8/8/8/8/P7/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            8,
-            2,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 2);
+        let ret = pawn_moves(Piece::pawn(White), 8, 2, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn white_pawn_has_moved() {
         let b = BoardState::from_fen("8/8/8/8/8/3P4/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            7,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 1);
+        let ret = pawn_moves(Piece::pawn(White), 7, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
     fn white_pawn_cant_move_black_piece_block() {
         let b = BoardState::from_fen("8/8/8/8/3r4/3P4/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            7,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 0);
+        let ret = pawn_moves(Piece::pawn(White), 7, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 0);
     }
 
     #[test]
     fn white_pawn_cant_move_white_piece_block() {
         let b = BoardState::from_fen("8/8/8/8/3K4/3P4/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            7,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 0);
+        let ret = pawn_moves(Piece::pawn(White), 7, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 0);
     }
 
     #[test]
     fn white_pawn_with_two_captures_and_start() {
         let b = BoardState::from_fen("8/8/8/8/8/n1q5/1P6/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            8,
-            3,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 4);
+        let ret = pawn_moves(Piece::pawn(White), 8, 3, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 4);
     }
 
     #[test]
     fn white_pawn_with_one_capture() {
         let b = BoardState::from_fen("8/8/Q1b5/1P6/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            5,
-            3,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 2);
+        let ret = pawn_moves(Piece::pawn(White), 5, 3, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn white_pawn_double_push_piece_in_front() {
         let b = BoardState::from_fen("8/8/8/8/8/b7/P7/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(White),
-            8,
-            2,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 0);
+        let ret = pawn_moves(Piece::pawn(White), 8, 2, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 0);
     }
 
     #[test]
@@ -1203,76 +969,36 @@ mod tests {
     #[test]
     fn black_pawn_double_push() {
         let b = BoardState::from_fen("8/p7/8/8/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(Black),
-            3,
-            2,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 2);
+        let ret = pawn_moves(Piece::pawn(Black), 3, 2, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn black_pawn_has_moved() {
         let b = BoardState::from_fen("8/8/8/3p4/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(Black),
-            5,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 1);
+        let ret = pawn_moves(Piece::pawn(Black), 5, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
     fn black_pawn_cant_move_white_piece_block() {
         let b = BoardState::from_fen("8/3p4/3R4/8/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(Black),
-            3,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 0);
+        let ret = pawn_moves(Piece::pawn(Black), 3, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 0);
     }
 
     #[test]
     fn black_pawn_with_two_captures_and_start() {
         let b = BoardState::from_fen("8/3p4/2R1R3/8/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(Black),
-            3,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 4);
+        let ret = pawn_moves(Piece::pawn(Black), 3, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 4);
     }
 
     #[test]
     fn black_pawn_with_one_capture() {
         let b = BoardState::from_fen("8/3p4/3qR3/8/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
-            Piece::pawn(Black),
-            3,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 1);
+        let ret = pawn_moves(Piece::pawn(Black), 3, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
@@ -1304,61 +1030,29 @@ mod tests {
     #[test]
     fn king_empty_board_center() {
         let b = BoardState::from_fen("8/8/8/8/3K4/8/8/k7 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        king_moves(
-            Piece::king(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(dbg!(ret).len(), 8);
+        let ret = king_moves(Piece::king(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(dbg!(ret).count_ones(), 8);
     }
 
     #[test]
     fn king_start_pos() {
         let b = BoardState::from_fen("8/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        king_moves(
-            Piece::king(White),
-            9,
-            6,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 5);
+        let ret = king_moves(Piece::king(White), 9, 6, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 5);
     }
 
     #[test]
     fn king_start_pos_other_pieces() {
         let b = BoardState::from_fen("8/8/8/8/8/8/3Pn3/3QKB2 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        king_moves(
-            Piece::king(White),
-            9,
-            6,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 2);
+        let ret = king_moves(Piece::king(White), 9, 6, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn king_black_other_pieces() {
         let b = BoardState::from_fen("8/8/8/8/8/3Pn3/3QkB2/3R1q2 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        king_moves(
-            Piece::king(Black),
-            8,
-            6,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 6);
+        let ret = king_moves(Piece::king(Black), 8, 6, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 6);
     }
 
     // Rook tests
@@ -1366,75 +1060,35 @@ mod tests {
     #[test]
     fn rook_center_of_empty_board() {
         let b = BoardState::from_fen("8/8/8/8/3R4/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
-            Piece::rook(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 14);
+        let ret = rook_moves(Piece::rook(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 14);
     }
 
     #[test]
     fn rook_center_of_board() {
         let b = BoardState::from_fen("8/8/8/3q4/2kRp3/3b4/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
-            Piece::rook(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 4);
+        let ret = rook_moves(Piece::rook(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 4);
     }
 
     #[test]
     fn rook_center_of_board_with_white_pieces() {
         let b = BoardState::from_fen("7p/3N4/8/4n3/2kR4/3b4/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
-            Piece::rook(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 8);
+        let ret = rook_moves(Piece::rook(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 8);
     }
 
     #[test]
     fn rook_corner() {
         let b = BoardState::from_fen("7p/3N4/K7/4n3/2kR4/3b4/8/7R w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
-            Piece::rook(White),
-            9,
-            9,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 14);
+        let ret = rook_moves(Piece::rook(White), 9, 9, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 14);
     }
     #[test]
     fn black_rook_center_of_board_with_white_pieces() {
         let b = BoardState::from_fen("7p/3N4/8/4n3/2kr4/3b4/8/K7 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
-            Piece::rook(Black),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 7);
+        let ret = rook_moves(Piece::rook(Black), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 7);
     }
 
     // Bishop tests
@@ -1442,61 +1096,29 @@ mod tests {
     #[test]
     fn black_bishop_center_empty_board() {
         let b = BoardState::from_fen("8/8/8/3b4/8/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        bishop_moves(
-            Piece::bishop(Black),
-            5,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 13);
+        let ret = bishop_moves(Piece::bishop(Black), 5, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 13);
     }
 
     #[test]
     fn black_bishop_center_with_captures() {
         let b = BoardState::from_fen("6P1/8/8/3b4/8/1R6/8/3Q4 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        bishop_moves(
-            Piece::bishop(Black),
-            5,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 12);
+        let ret = bishop_moves(Piece::bishop(Black), 5, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 12);
     }
 
     #[test]
     fn black_bishop_center_with_captures_and_black_pieces() {
         let b = BoardState::from_fen("6P1/8/2Q5/3b4/2k1n3/1R6/8/b2Q4 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        bishop_moves(
-            Piece::bishop(Black),
-            5,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 4);
+        let ret = bishop_moves(Piece::bishop(Black), 5, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 4);
     }
 
     #[test]
     fn white_bishop_center_with_captures_and_white_pieces() {
         let b = BoardState::from_fen("8/8/8/4r3/5B2/8/3Q4/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        bishop_moves(
-            Piece::bishop(White),
-            6,
-            7,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 6);
+        let ret = bishop_moves(Piece::bishop(White), 6, 7, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 6);
     }
 
     // Queen tests
@@ -1504,46 +1126,22 @@ mod tests {
     #[test]
     fn white_queen_empty_board() {
         let b = BoardState::from_fen("8/8/8/8/3Q4/8/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        queen_moves(
-            Piece::queen(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 27);
+        let ret = queen_moves(Piece::queen(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 27);
     }
 
     #[test]
     fn white_queen_cant_move() {
         let b = BoardState::from_fen("8/8/8/2NBR3/2PQR3/2RRR3/8/8 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        queen_moves(
-            Piece::queen(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 0);
+        let ret = queen_moves(Piece::queen(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 0);
     }
 
     #[test]
     fn white_queen_with_other_piece() {
         let b = BoardState::from_fen("8/6r1/8/8/3Q4/5N2/8/6P1 w - - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        queen_moves(
-            Piece::queen(White),
-            6,
-            5,
-            &b,
-            &mut ret,
-            MoveGenerationMode::AllMoves,
-        );
-        assert_eq!(ret.len(), 25);
+        let ret = queen_moves(Piece::queen(White), 6, 5, &b, MoveGenerationMode::AllMoves);
+        assert_eq!(ret.count_ones(), 25);
     }
 
     // Castling tests
@@ -1659,120 +1257,106 @@ mod tests {
     #[test]
     fn generate_only_captures_queen() {
         let b = BoardState::from_fen("q3b3/1Q3n2/8/8/1R6/8/8/p6b w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        queen_moves(
+        let ret = queen_moves(
             Piece::queen(White),
             3,
             3,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 3);
+        assert_eq!(ret.count_ones(), 3);
     }
 
     #[test]
     fn generate_only_captures_bishop() {
         let b = BoardState::from_fen("q3b3/1B6/8/8/R7/8/8/p6b w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        bishop_moves(
+        let ret = bishop_moves(
             Piece::bishop(White),
             3,
             3,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 2);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn generate_only_captures_rook() {
         let b = BoardState::from_fen("R3b3/8/8/8/R7/8/8/p7 w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        rook_moves(
+        let ret = rook_moves(
             Piece::rook(White),
             2,
             2,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 1);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
     fn generate_only_captures_king() {
         let b = BoardState::from_fen("q3b3/1Kr2n2/1B6/8/1R6/8/8/p6b w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        king_moves(
+        let ret = king_moves(
             Piece::king(White),
             3,
             3,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 2);
+        assert_eq!(ret.count_ones(), 2);
     }
 
     #[test]
     fn generate_only_captures_knight() {
         let b = BoardState::from_fen("q3b3/1Nr2n2/1B6/2b5/1R6/8/8/p7 w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        knight_moves(
+        let ret = knight_moves(
             Piece::knight(White),
             3,
             3,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 1);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
     fn generate_only_captures_pawn() {
         let b = BoardState::from_fen("q3b3/1Pr2n2/1B6/2b5/1R6/8/8/p7 w KQkq - 0 1").unwrap();
-        let mut ret: Vec<Point> = Vec::new();
-        pawn_moves(
+        let ret = pawn_moves(
             Piece::knight(White),
             3,
             3,
             &b,
-            &mut ret,
             MoveGenerationMode::CapturesOnly,
         );
-        assert_eq!(ret.len(), 1);
+        assert_eq!(ret.count_ones(), 1);
     }
 
     #[test]
     fn only_captures_correctly_counted() {
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-
         let b = BoardState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
             .unwrap();
         assert_eq!(
-            generate_moves(&b, MoveGenerationMode::CapturesOnly, &zobrist_hasher).len(),
+            generate_moves(&b, MoveGenerationMode::CapturesOnly).len(),
             0
         );
 
         let b = BoardState::from_fen("rnbqkbnr/pppppppp/2N5/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
             .unwrap();
         assert_eq!(
-            generate_moves(&b, MoveGenerationMode::CapturesOnly, &zobrist_hasher).len(),
+            generate_moves(&b, MoveGenerationMode::CapturesOnly).len(),
             4
         );
 
         let b = BoardState::from_fen("K1k4p/8/8/8/8/8/8/B6R w KQkq - 0 1").unwrap();
         assert_eq!(
-            generate_moves(&b, MoveGenerationMode::CapturesOnly, &zobrist_hasher).len(),
+            generate_moves(&b, MoveGenerationMode::CapturesOnly).len(),
             2
         );
 
         let b = BoardState::from_fen("5B2/8/8/2p4R/1PK5/3NQ3/8/2R5 w KQkq - 0 1").unwrap();
         assert_eq!(
-            generate_moves(&b, MoveGenerationMode::CapturesOnly, &zobrist_hasher).len(),
+            generate_moves(&b, MoveGenerationMode::CapturesOnly).len(),
             6
         );
     }
@@ -1785,15 +1369,94 @@ mod tests {
         assert!(MVV_LVA[Queen.index()][Queen.index()] > MVV_LVA[Rook.index()][Rook.index()]);
     }
 
+    fn find_move(board: &BoardState, mov: &str) -> Move {
+        generate_moves(board, MoveGenerationMode::AllMoves)
+            .into_iter()
+            .find(|m| m.to_string() == mov)
+            .unwrap()
+    }
+
+    #[test]
+    fn static_exchange_evaluation() {
+        // undefended pawn
+        let b = BoardState::from_fen("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1").unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "e1e5")), 100);
+
+        // knight for a pawn, recapturing any further only loses more
+        let b = BoardState::from_fen("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1")
+            .unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "d3e5")), -220);
+
+        // queen takes a pawn defended by a pawn
+        let b = BoardState::from_fen("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1").unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "e1e5")), -800);
+
+        // the rook behind joins in once the first rook has captured
+        let b = BoardState::from_fen("4r1k1/8/8/4p3/8/8/4R3/4R1K1 w - - 0 1").unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "e2e5")), 100);
+
+        // en passant, the captured pawn is not on the target square
+        let b = BoardState::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "e5d6")), 100);
+        let b = BoardState::from_fen("4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(static_exchange(&b, find_move(&b, "e5d6")), 0);
+    }
+
+    #[test]
+    fn losing_captures_ordered_after_killer_moves() {
+        let b = BoardState::from_fen("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1").unwrap();
+        let bad_capture = order_heuristic(&b, find_move(&b, "e1e5"));
+        assert!(bad_capture > 0 && bad_capture < 25);
+        assert_eq!(order_heuristic(&b, find_move(&b, "e1e2")), 0);
+
+        let b = BoardState::from_fen("4k3/8/8/4p3/8/8/8/4QK2 w - - 0 1").unwrap();
+        assert!(order_heuristic(&b, find_move(&b, "e1e5")) >= GOOD_CAPTURE_SCORE);
+    }
+
     // Perft tests - move generation. Table of values taken from https://www.chessprogramming.org/Perft_Results
 
+    #[test]
+    fn perft_divide_start_position() {
+        let mut b = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let divide = perft_divide(&mut b, 3, &zobrist_hasher);
+        assert_eq!(divide.len(), 20);
+        assert_eq!(divide.iter().map(|(_, nodes)| nodes).sum::<u64>(), 8902);
+        let (_, e2e4) = divide
+            .iter()
+            .find(|(mov, _)| mov.to_string() == "e2e4")
+            .unwrap();
+        assert_eq!(*e2e4, 600);
+        // the board is left as it was
+        assert_eq!(b.to_fen(), DEFAULT_FEN_STRING);
+        assert_eq!(perft(&mut b, 0, &zobrist_hasher), 1);
+        assert!(perft_divide(&mut b, 0, &zobrist_hasher).is_empty());
+    }
+
+    #[test]
+    fn perft_matches_counted_moves() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        for fen in PERFT_POSITIONS {
+            let mut b = BoardState::from_fen(fen).unwrap();
+            let mut moves_states = [0; 3];
+            generate_moves_test(&mut b, 0, 3, &mut moves_states, false, &zobrist_hasher);
+            assert_eq!(
+                perft(&mut b, 3, &zobrist_hasher),
+                moves_states[2],
+                "{}",
+                fen
+            );
+        }
+    }
+
     #[test]
     fn perft_test_position_1() {
         let mut moves_states = [0; 5];
-        let b = BoardState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
-            .unwrap();
+        let mut b =
+            BoardState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+                .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 5, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 5, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 20);
         assert_eq!(moves_states[1], 400);
         assert_eq!(moves_states[2], 8902);
@@ -1804,12 +1467,12 @@ mod tests {
     #[test]
     fn perft_test_position_2() {
         let mut moves_states = [0; 4];
-        let b = BoardState::from_fen(
+        let mut b = BoardState::from_fen(
             "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
         )
         .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 4, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 4, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 48);
         assert_eq!(moves_states[1], 2039);
         assert_eq!(moves_states[2], 97862);
@@ -1819,9 +1482,9 @@ mod tests {
     #[test]
     fn perft_test_position_3() {
         let mut moves_states = [0; 5];
-        let b = BoardState::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        let mut b = BoardState::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 5, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 5, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 14);
         assert_eq!(moves_states[1], 191);
         assert_eq!(moves_states[2], 2812);
@@ -1832,12 +1495,12 @@ mod tests {
     #[test]
     fn perft_test_position_4() {
         let mut moves_states = [0; 4];
-        let b = BoardState::from_fen(
+        let mut b = BoardState::from_fen(
             "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
         )
         .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 4, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 4, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 6);
         assert_eq!(moves_states[1], 264);
         assert_eq!(moves_states[2], 9467);
@@ -1847,25 +1510,135 @@ mod tests {
     #[test]
     fn perft_test_position_4_mirrored() {
         let mut moves_states = [0; 4];
-        let b = BoardState::from_fen(
+        let mut b = BoardState::from_fen(
             "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
         )
         .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 4, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 4, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 6);
         assert_eq!(moves_states[1], 264);
         assert_eq!(moves_states[2], 9467);
         assert_eq!(moves_states[3], 422333);
     }
 
+    // Fen round trip tests
+
+    const PERFT_POSITIONS: [&str; 7] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    ];
+
+    // every position reachable within depth plies should survive a trip through fen unchanged
+    fn fen_round_trip(board: &mut BoardState, depth: usize, zobrist_hasher: &ZobristHasher) {
+        let fen = board.to_fen();
+        let parsed = BoardState::from_fen(&fen).unwrap();
+        assert_eq!(parsed.to_fen(), fen);
+        assert_eq!(parsed.zobrist_key, board.zobrist_key, "{}", fen);
+        assert_eq!(parsed.white_king_location, board.white_king_location);
+        assert_eq!(parsed.black_king_location, board.black_king_location);
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(board, MoveGenerationMode::AllMoves) {
+            let undo = board.make_move(mov, zobrist_hasher);
+            fen_round_trip(board, depth - 1, zobrist_hasher);
+            board.unmake_move(mov, &undo);
+            // taking back the move should leave the board exactly as it was
+            assert_eq!(board.to_fen(), fen);
+            assert_eq!(board.zobrist_key, parsed.zobrist_key);
+        }
+    }
+
+    #[test]
+    fn fen_round_trip_perft_positions() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        for fen in PERFT_POSITIONS {
+            let mut b = BoardState::from_fen(fen).unwrap();
+            assert_eq!(b.to_fen(), fen);
+            fen_round_trip(&mut b, 2, &zobrist_hasher);
+        }
+    }
+
+    // Move clock tests
+
+    // play a move on a copy of the board
+    fn after_move(board: &BoardState, mov: Move, zobrist_hasher: &ZobristHasher) -> BoardState {
+        let mut board = board.clone();
+        board.make_move(mov, zobrist_hasher);
+        board
+    }
+
+    #[test]
+    fn move_clocks_quiet_move() {
+        let b = BoardState::from_fen("4k3/8/8/8/8/8/4P3/R3K3 w Q - 12 40").unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let moves = generate_moves(&b, MoveGenerationMode::AllMoves);
+        for &mov in &moves {
+            let start = mov.from();
+            let new_board = after_move(&b, mov, &zobrist_hasher);
+            if b.board[start.0][start.1] == Piece::pawn(White) {
+                assert_eq!(new_board.half_move_clock, 0);
+            } else {
+                // includes castling
+                assert_eq!(new_board.half_move_clock, 13);
+            }
+            // only incremented after black moves
+            assert_eq!(new_board.full_move_number, 40);
+        }
+    }
+
+    #[test]
+    fn move_clocks_black_capture() {
+        let b = BoardState::from_fen("4k3/8/8/8/8/8/1n6/R3K3 b Q - 12 40").unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let moves = generate_moves(&b, MoveGenerationMode::AllMoves);
+        for &mov in &moves {
+            let end = mov.to();
+            let new_board = after_move(&b, mov, &zobrist_hasher);
+            if b.board[end.0][end.1].is_empty() {
+                assert_eq!(new_board.half_move_clock, 13);
+            } else {
+                assert_eq!(new_board.half_move_clock, 0);
+            }
+            assert_eq!(new_board.full_move_number, 41);
+        }
+    }
+
+    #[test]
+    fn move_clocks_en_passant_and_promotion() {
+        let b = BoardState::from_fen("4k3/1P6/8/3pP3/8/8/8/4K3 w - d6 20 60").unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let moves = generate_moves(&b, MoveGenerationMode::AllMoves);
+        let en_passant = *moves.iter().find(|mov| mov.is_en_passant()).unwrap();
+        assert_eq!(en_passant.to_string(), "e5d6");
+        assert_eq!(
+            after_move(&b, en_passant, &zobrist_hasher).half_move_clock,
+            0
+        );
+        let promotions = moves.iter().filter(|mov| mov.promotion.is_some());
+        assert_eq!(promotions.clone().count(), 4);
+        for &promotion in promotions {
+            assert_eq!(
+                after_move(&b, promotion, &zobrist_hasher).half_move_clock,
+                0
+            );
+        }
+    }
+
     #[test]
     fn perft_test_position_5() {
         let mut moves_states = [0; 4];
-        let b = BoardState::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8")
-            .unwrap();
+        let mut b =
+            BoardState::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8")
+                .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 4, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 4, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 44);
         assert_eq!(moves_states[1], 1486);
         assert_eq!(moves_states[2], 62379);
@@ -1875,12 +1648,12 @@ mod tests {
     #[test]
     fn perft_test_position_6() {
         let mut moves_states = [0; 4];
-        let b = BoardState::from_fen(
+        let mut b = BoardState::from_fen(
             "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
         )
         .unwrap();
         let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        generate_moves_test(&b, 0, 4, &mut moves_states, false, &zobrist_hasher);
+        generate_moves_test(&mut b, 0, 4, &mut moves_states, false, &zobrist_hasher);
         assert_eq!(moves_states[0], 46);
         assert_eq!(moves_states[1], 2079);
         assert_eq!(moves_states[2], 89890);
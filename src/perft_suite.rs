@@ -0,0 +1,206 @@
+use crate::board::{BoardState, Move};
+use crate::move_generation::{perft, perft_divide};
+use crate::zobrist::ZobristHasher;
+use std::fmt;
+
+/*
+    Check the move generator against a perft suite in the EPD format, each line is a position
+    followed by the expected leaf node count at each depth
+
+    rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - ;D1 20 ;D2 400 ;D3 8902
+
+    The bundled suite covers the well known perft positions along with the positions from
+    Martin Sedlak's perft tests and the perftsuite.epd of the Roce chess engine, every count
+    in it is published, the sources are listed in the file. Copies of these positions with
+    the colors swapped or mirrored from left to right fill out the rest, a mirrored position
+    has the same counts as the original
+*/
+pub const BUNDLED_SUITE: &str = include_str!("../res/perft_suite.epd");
+
+pub struct PerftEntry {
+    pub fen: String,
+    pub depths: Vec<(usize, u64)>, // (depth, expected leaf nodes)
+}
+
+/*
+    The first depth where the move generator disagrees with the suite, the divide
+    at that depth can be compared with the output of another engine to find the bad move
+*/
+pub struct PerftMismatch {
+    pub fen: String,
+    pub depth: usize,
+    pub expected: u64,
+    pub found: u64,
+    pub divide: Vec<(Move, u64)>,
+}
+
+impl fmt::Display for PerftMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} depth {}: expected {} nodes but found {}",
+            self.fen, self.depth, self.expected, self.found
+        )?;
+        for (mov, nodes) in &self.divide {
+            writeln!(f, "{}: {}", mov, nodes)?;
+        }
+        Ok(())
+    }
+}
+
+// Parse a single line of a perft EPD file
+pub fn parse_epd_line(line: &str) -> Result<PerftEntry, &'static str> {
+    let mut fields = line.split(';');
+    let position = fields.next().unwrap_or_default().trim();
+    let fen = match position.split(' ').count() {
+        // EPD leaves out the move clocks, they do not change the node counts
+        4 => format!("{} 0 1", position),
+        6 => position.to_string(),
+        _ => return Err("Could not parse EPD line: Invalid position"),
+    };
+    BoardState::from_fen(&fen)?;
+
+    let mut depths = Vec::new();
+    for field in fields {
+        let (depth, nodes) = match field.trim().split_once(' ') {
+            Some((depth, nodes)) => (depth, nodes),
+            None => return Err("Could not parse EPD line: Invalid depth entry"),
+        };
+        let depth = match depth.strip_prefix('D').map(str::parse::<usize>) {
+            Some(Ok(depth)) if depth > 0 => depth,
+            _ => return Err("Could not parse EPD line: Invalid depth"),
+        };
+        let nodes = match nodes.trim().parse::<u64>() {
+            Ok(nodes) => nodes,
+            Err(_) => return Err("Could not parse EPD line: Invalid node count"),
+        };
+        depths.push((depth, nodes));
+    }
+
+    if depths.is_empty() {
+        return Err("Could not parse EPD line: No depths given");
+    }
+    Ok(PerftEntry { fen, depths })
+}
+
+// Parse every position in a perft EPD file, blank lines and lines starting with # are skipped
+pub fn parse_epd(epd: &str) -> Result<Vec<PerftEntry>, &'static str> {
+    epd.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_epd_line)
+        .collect()
+}
+
+/*
+    Run perft at every depth listed for this position, depths expected to have more than
+    max_nodes leaf nodes are skipped to keep the run short
+
+    Returns the number of leaf nodes visited
+*/
+pub fn check_perft_entry(
+    entry: &PerftEntry,
+    max_nodes: Option<u64>,
+    zobrist_hasher: &ZobristHasher,
+) -> Result<u64, PerftMismatch> {
+    let mut board = BoardState::from_fen(&entry.fen).unwrap();
+    let mut total_nodes = 0;
+    for &(depth, expected) in &entry.depths {
+        if max_nodes.is_some_and(|max_nodes| expected > max_nodes) {
+            continue;
+        }
+        let found = perft(&mut board, depth, zobrist_hasher);
+        if found != expected {
+            return Err(PerftMismatch {
+                fen: entry.fen.clone(),
+                depth,
+                expected,
+                found,
+                divide: perft_divide(&mut board, depth, zobrist_hasher),
+            });
+        }
+        total_nodes += found;
+    }
+    Ok(total_nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_epd_entries() {
+        let entry = parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K - ;D1 15 ;D2 66 ;D3 1197").unwrap();
+        assert_eq!(entry.fen, "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        assert_eq!(entry.depths, vec![(1, 15), (2, 66), (3, 1197)]);
+
+        let entry = parse_epd_line("4k3/8/8/8/8/8/8/4K2R b K - 3 40 ;D1 5").unwrap();
+        assert_eq!(entry.fen, "4k3/8/8/8/8/8/8/4K2R b K - 3 40");
+
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K -").is_err());
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K - ;D1").is_err());
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K - ;D0 1").is_err());
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K - ;D1 x").is_err());
+        assert!(parse_epd_line("4k3/8/8/8/8/8/8/4K2X w K - ;D1 15").is_err());
+
+        let suite = "# comment\n\n4k3/8/8/8/8/8/8/4K2R w K - ;D1 15\n";
+        assert_eq!(parse_epd(suite).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn perft_mismatch_reports_divide() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let entry = parse_epd_line("4k3/8/8/8/8/8/8/4K2R w K - ;D1 15 ;D2 67").unwrap();
+        let mismatch = check_perft_entry(&entry, None, &zobrist_hasher).unwrap_err();
+        assert_eq!(
+            (mismatch.depth, mismatch.expected, mismatch.found),
+            (2, 67, 66)
+        );
+        assert_eq!(mismatch.divide.len(), 15);
+        let report = mismatch.to_string();
+        assert!(report.contains("expected 67 nodes but found 66"));
+        assert!(report.contains("e1g1: 3"));
+    }
+
+    // the start position and positions 2 to 6 from the chess programming wiki lead the suite
+    const CANONICAL_POSITIONS: usize = 6;
+    // followed by Martin Sedlak's special cases and the same positions with the colors swapped,
+    // which only have a count at their deepest depth
+    const SPECIAL_CASES: usize = 28;
+
+    fn check_suite(suite: &[PerftEntry], max_nodes: Option<u64>) {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mismatches: Vec<String> = suite
+            .iter()
+            .filter_map(|entry| check_perft_entry(entry, max_nodes, &zobrist_hasher).err())
+            .map(|mismatch| mismatch.to_string())
+            .collect();
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn perft_bundled_suite() {
+        let suite = parse_epd(BUNDLED_SUITE).unwrap();
+        assert!(suite.len() >= 290);
+        check_suite(&suite, Some(200_000));
+    }
+
+    #[test]
+    fn perft_special_cases() {
+        let suite = parse_epd(BUNDLED_SUITE).unwrap();
+        let special_cases = &suite[CANONICAL_POSITIONS..CANONICAL_POSITIONS + SPECIAL_CASES];
+        assert!(special_cases.iter().all(|entry| entry.depths.len() == 1));
+        check_suite(special_cases, None);
+    }
+
+    // takes minutes without optimizations, run with cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn perft_canonical_positions_every_depth() {
+        let suite = parse_epd(BUNDLED_SUITE).unwrap();
+        assert!(suite[1]
+            .fen
+            .starts_with("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R"));
+        check_suite(&suite[..CANONICAL_POSITIONS], None);
+    }
+}
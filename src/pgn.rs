@@ -0,0 +1,570 @@
+use crate::board::{BoardState, PieceColor, DEFAULT_FEN_STRING};
+use crate::engine::MATE_SCORE;
+use crate::san::move_from_san;
+use crate::zobrist::ZobristHasher;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+    Portable Game Notation, the standard format for recording games
+    See https://www.saremco.com/Files/PGN_Standard.txt
+
+    [Event "Walleye self-play"]
+    ...
+    [Result "1-0"]
+
+    1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# {White wins by checkmate} 1-0
+*/
+// export format keeps movetext lines to at most 80 characters
+const MAX_LINE_LENGTH: usize = 80;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unfinished,
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                GameResult::WhiteWins => "1-0",
+                GameResult::BlackWins => "0-1",
+                GameResult::Draw => "1/2-1/2",
+                GameResult::Unfinished => "*",
+            }
+        )
+    }
+}
+
+pub struct PgnMove {
+    pub san: String,
+    pub eval: Option<i32>, // search score from white's point of view
+}
+
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>, // written out in this order
+    pub moves: Vec<PgnMove>,
+    pub result: GameResult,
+    pub termination: Option<String>, // why the game ended, written as a comment after the last move
+    start_color: PieceColor,
+    start_move_number: u32,
+}
+
+impl PgnGame {
+    /*
+        Start a game from this position with the Seven Tag Roster filled in, the SetUp and FEN
+        tags are added when the game does not start from the usual starting position
+    */
+    pub fn new(event: &str, white: &str, black: &str, start: &BoardState) -> PgnGame {
+        let mut game = PgnGame {
+            tags: Vec::new(),
+            moves: Vec::new(),
+            result: GameResult::Unfinished,
+            termination: None,
+            start_color: start.to_move,
+            start_move_number: start.full_move_number,
+        };
+        game.set_tag("Event", event);
+        game.set_tag("Site", "?");
+        game.set_tag("Date", &pgn_date(SystemTime::now()));
+        game.set_tag("Round", "-");
+        game.set_tag("White", white);
+        game.set_tag("Black", black);
+        game.set_tag("Result", &GameResult::Unfinished.to_string());
+        let fen = start.to_fen();
+        if fen != DEFAULT_FEN_STRING {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &fen);
+        }
+        game
+    }
+
+    // Replace the value of a tag, or add it to the end if it is not already there
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old_value)) => *old_value = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn add_move(&mut self, san: String, eval: Option<i32>) {
+        self.moves.push(PgnMove { san, eval });
+    }
+
+    /*
+        Record how the game ended, games stopped before a result was reached
+        should be marked as GameResult::Unfinished
+    */
+    pub fn set_result(&mut self, result: GameResult, termination: &str) {
+        self.result = result;
+        self.termination = Some(termination.to_string());
+        self.set_tag("Result", &result.to_string());
+        let termination_tag = match result {
+            GameResult::Unfinished => "unterminated",
+            _ => "normal",
+        };
+        self.set_tag("Termination", termination_tag);
+    }
+
+    /*
+        Write the game out in the PGN export format, with_evals adds the search
+        score after each move as a {[%eval ...]} comment
+    */
+    pub fn to_pgn(&self, with_evals: bool) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut color = self.start_color;
+        let mut move_number = self.start_move_number;
+        // black's move needs its own number at the start and after a comment
+        let mut needs_number = true;
+        for mov in &self.moves {
+            match color {
+                PieceColor::White => tokens.push(format!("{}.", move_number)),
+                PieceColor::Black if needs_number => tokens.push(format!("{}...", move_number)),
+                PieceColor::Black => {}
+            }
+            tokens.push(mov.san.clone());
+            needs_number = false;
+            if let Some(eval) = mov.eval.filter(|_| with_evals) {
+                tokens.push(format!("{{[%eval {}]}}", eval_to_pgn(eval)));
+                needs_number = true;
+            }
+            if color == PieceColor::Black {
+                move_number += 1;
+            }
+            color = color.opposite();
+        }
+        if let Some(termination) = &self.termination {
+            tokens.push(format!("{{{}}}", termination.replace('}', ")")));
+        }
+        tokens.push(self.result.to_string());
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + token.len() + 1 > MAX_LINE_LENGTH {
+                pgn.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                pgn.push(' ');
+                line_length += 1;
+            }
+            pgn.push_str(&token);
+            line_length += token.len();
+        }
+        pgn.push('\n');
+        pgn
+    }
+
+    // The position the game starts from, given by the FEN tag if there is one
+    pub fn start_position(&self) -> Result<BoardState, &'static str> {
+        BoardState::from_fen(self.tag("FEN").unwrap_or(DEFAULT_FEN_STRING))
+    }
+
+    /*
+        Replay the game, returns the position before the first move followed by
+        the position after every move. Moves are checked against the move generator
+        so an illegal or ambiguous move is reported as an error
+    */
+    pub fn positions(&self) -> Result<Vec<BoardState>, &'static str> {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mut board = self.start_position()?;
+        let mut positions = Vec::with_capacity(self.moves.len() + 1);
+        positions.push(board.clone());
+        for mov in &self.moves {
+            let mov = move_from_san(&board, &mov.san)?;
+            board.make_move(mov, &zobrist_hasher);
+            positions.push(board.clone());
+        }
+        Ok(positions)
+    }
+}
+
+/*
+    Read every game in a PGN file, the tags and moves of each game are kept while comments,
+    NAGs ($1), variations and move numbers are skipped
+
+    The moves are only parsed here, use PgnGame::positions to check they are legal
+*/
+pub fn parse_pgn(pgn: &str) -> Result<Vec<PgnGame>, &'static str> {
+    let mut games = Vec::new();
+    let mut game = empty_game();
+    let mut variation_depth = 0;
+    let mut chars = pgn.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        let at_line_start = line_start;
+        line_start = c == '\n';
+        match c {
+            // escaped lines and rest of line comments, the newline ending them starts a new line
+            '%' if at_line_start => {
+                skip_line(&mut chars);
+                line_start = true;
+            }
+            ';' => {
+                skip_line(&mut chars);
+                line_start = true;
+            }
+            '{' => {
+                if !chars.by_ref().any(|c| c == '}') {
+                    return Err("Could not parse PGN: Unterminated comment");
+                }
+            }
+            '(' => variation_depth += 1,
+            ')' if variation_depth == 0 => {
+                return Err("Could not parse PGN: Unmatched closing parenthesis")
+            }
+            ')' => variation_depth -= 1,
+            '[' if variation_depth == 0 => {
+                // tags after movetext start the next game
+                if !game.moves.is_empty() {
+                    games.push(finish_game(game, None)?);
+                    game = empty_game();
+                }
+                let (name, value) = parse_tag(&mut chars)?;
+                game.set_tag(&name, &value);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "{}()[];".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                if variation_depth > 0 || token.starts_with('$') {
+                    continue;
+                }
+                if let Some(result) = parse_result(&token) {
+                    games.push(finish_game(game, Some(result))?);
+                    game = empty_game();
+                    continue;
+                }
+                // move numbers can be written right up against the move (ex: 1.e4)
+                let without_number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                let san = match without_number.strip_prefix('.') {
+                    Some(san) => san.trim_start_matches('.'),
+                    None => &token,
+                };
+                if !san.is_empty() {
+                    game.add_move(san.to_string(), None);
+                }
+            }
+        }
+    }
+
+    if variation_depth > 0 {
+        return Err("Could not parse PGN: Unterminated variation");
+    }
+    if !game.tags.is_empty() || !game.moves.is_empty() {
+        games.push(finish_game(game, None)?);
+    }
+    Ok(games)
+}
+
+fn empty_game() -> PgnGame {
+    PgnGame {
+        tags: Vec::new(),
+        moves: Vec::new(),
+        result: GameResult::Unfinished,
+        termination: None,
+        start_color: PieceColor::White,
+        start_move_number: 1,
+    }
+}
+
+// Fill in the details of a game that can only be known once all of it has been read
+fn finish_game(mut game: PgnGame, result: Option<GameResult>) -> Result<PgnGame, &'static str> {
+    let start = game.start_position()?;
+    game.start_color = start.to_move;
+    game.start_move_number = start.full_move_number;
+    game.result = result
+        .or_else(|| game.tag("Result").and_then(parse_result))
+        .unwrap_or(GameResult::Unfinished);
+    Ok(game)
+}
+
+fn parse_result(token: &str) -> Option<GameResult> {
+    match token {
+        "1-0" => Some(GameResult::WhiteWins),
+        "0-1" => Some(GameResult::BlackWins),
+        "1/2-1/2" => Some(GameResult::Draw),
+        "*" => Some(GameResult::Unfinished),
+        _ => None,
+    }
+}
+
+fn skip_line(chars: &mut impl Iterator<Item = char>) {
+    for c in chars {
+        if c == '\n' {
+            break;
+        }
+    }
+}
+
+// Parse the rest of a tag pair after the opening bracket (ex: Event "Casual Game"])
+fn parse_tag(chars: &mut impl Iterator<Item = char>) -> Result<(String, String), &'static str> {
+    let mut chars = chars.skip_while(|c| c.is_whitespace());
+    let mut name = String::new();
+    for c in chars.by_ref() {
+        match c {
+            '"' => break,
+            c if c.is_whitespace() => {}
+            c => name.push(c),
+        }
+    }
+
+    let mut value = String::new();
+    let mut closed = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            '"' => {
+                closed = true;
+                break;
+            }
+            c => value.push(c),
+        }
+    }
+
+    if name.is_empty() || !closed || !chars.any(|c| c == ']') {
+        return Err("Could not parse PGN: Invalid tag");
+    }
+    Ok((name, value))
+}
+
+/*
+    Format a score from white's point of view the way %eval comments expect,
+    pawns with two decimals or the number of moves until mate (ex: 0.35, -1.20, #3, #-2)
+*/
+fn eval_to_pgn(eval: i32) -> String {
+    let mate_window = 15;
+    if eval >= MATE_SCORE - mate_window {
+        format!("#{}", (MATE_SCORE - eval + 1) / 2)
+    } else if eval <= -MATE_SCORE + mate_window {
+        format!("#-{}", (MATE_SCORE + eval + 1) / 2)
+    } else {
+        let sign = if eval < 0 { "-" } else { "" };
+        format!("{}{}.{:02}", sign, eval.abs() / 100, eval.abs() % 100)
+    }
+}
+
+/*
+    Get the date in the YYYY.MM.DD format of the Date tag, converting days since the epoch
+    to a calendar date, see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+*/
+fn pgn_date(time: SystemTime) -> String {
+    let days = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() / 86400) as i64,
+        Err(_) => return "????.??.??".to_string(),
+    };
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn game_from(fen: &str, moves: &[&str]) -> PgnGame {
+        let board = BoardState::from_fen(fen).unwrap();
+        let mut game = PgnGame::new("Test", "Walleye", "Walleye", &board);
+        game.set_tag("Date", "2021.06.01");
+        for san in moves {
+            game.add_move(san.to_string(), None);
+        }
+        game
+    }
+
+    #[test]
+    fn pgn_export_start_position() {
+        let mut game = game_from(
+            DEFAULT_FEN_STRING,
+            &["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6"],
+        );
+        game.add_move("Qxf7#".to_string(), None);
+        game.set_result(GameResult::WhiteWins, "White wins by checkmate");
+        assert_eq!(
+            game.to_pgn(false),
+            "[Event \"Test\"]\n\
+             [Site \"?\"]\n\
+             [Date \"2021.06.01\"]\n\
+             [Round \"-\"]\n\
+             [White \"Walleye\"]\n\
+             [Black \"Walleye\"]\n\
+             [Result \"1-0\"]\n\
+             [Termination \"normal\"]\n\
+             \n\
+             1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# {White wins by checkmate} 1-0\n"
+        );
+        assert_eq!(game.tag("Result"), Some("1-0"));
+    }
+
+    #[test]
+    fn pgn_export_from_fen() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12";
+        let game = game_from(fen, &["Kd7", "e4", "Ke6"]);
+        let pgn = game.to_pgn(false);
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n"));
+        assert!(pgn.ends_with("\n12... Kd7 13. e4 Ke6 *\n"));
+    }
+
+    #[test]
+    fn pgn_export_evals() {
+        let mut game = game_from(DEFAULT_FEN_STRING, &[]);
+        game.add_move("e4".to_string(), Some(35));
+        game.add_move("e5".to_string(), Some(-120));
+        game.add_move("Qh5".to_string(), None);
+        game.add_move("Ke7".to_string(), Some(MATE_SCORE - 3));
+        game.add_move("Qxe5#".to_string(), Some(MATE_SCORE));
+        assert!(game
+            .to_pgn(false)
+            .ends_with("\n1. e4 e5 2. Qh5 Ke7 3. Qxe5# *\n"));
+        assert!(game.to_pgn(true).ends_with(
+            "\n1. e4 {[%eval 0.35]} 1... e5 {[%eval -1.20]} 2. Qh5 Ke7 {[%eval #2]} 3. Qxe5#\n\
+             {[%eval #0]} *\n"
+        ));
+        assert_eq!(eval_to_pgn(-MATE_SCORE + 4), "#-2");
+        assert_eq!(eval_to_pgn(-5), "-0.05");
+    }
+
+    #[test]
+    fn pgn_export_long_games_wrapped() {
+        let moves = ["Nf3", "Nf6", "Ng1", "Ng8"].repeat(20);
+        let pgn = game_from(DEFAULT_FEN_STRING, &moves).to_pgn(false);
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.lines().count() > 1);
+        assert!(movetext.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(movetext.starts_with("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3"));
+    }
+
+    #[test]
+    fn pgn_tags_escaped() {
+        let mut game = game_from(DEFAULT_FEN_STRING, &[]);
+        game.set_tag("Event", "The \"Big\" \\ Match");
+        assert!(game
+            .to_pgn(false)
+            .starts_with("[Event \"The \\\"Big\\\" \\\\ Match\"]\n"));
+    }
+
+    #[test]
+    fn pgn_dates() {
+        assert_eq!(pgn_date(UNIX_EPOCH), "1970.01.01");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(11016 * 86400 + 3600);
+        assert_eq!(pgn_date(leap_day), "2000.02.29");
+        let new_years_eve = UNIX_EPOCH + Duration::from_secs(19357 * 86400);
+        assert_eq!(pgn_date(new_years_eve), "2022.12.31");
+    }
+
+    #[test]
+    fn pgn_import_tags_and_moves() {
+        let pgn = "[Event \"Casual \\\"Blitz\\\" Game\"]\n\
+                   [Site \"?\"]\n\
+                   [Result \"1-0\"]\n\
+                   \n\
+                   % an escaped line\n\
+                   % and another one\n\
+                   1. e4 {best by test} e5 2.Qh5?! Nc6 $6 (2... g6 3. Qxe5+) 3. Bc4 Nf6?? ; oops\n\
+                   % escaped right after a comment 4. Qxe5+\n\
+                   4. Qxf7# 1-0\n";
+        let games = parse_pgn(pgn).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual \"Blitz\" Game"));
+        assert_eq!(game.result, GameResult::WhiteWins);
+        let moves: Vec<&str> = game.moves.iter().map(|mov| mov.san.as_str()).collect();
+        assert_eq!(
+            moves,
+            vec!["e4", "e5", "Qh5?!", "Nc6", "Bc4", "Nf6??", "Qxf7#"]
+        );
+
+        let positions = game.positions().unwrap();
+        assert_eq!(positions.len(), 8);
+        assert_eq!(positions[0].to_fen(), DEFAULT_FEN_STRING);
+        assert_eq!(
+            positions[7].to_fen(),
+            "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4"
+        );
+    }
+
+    #[test]
+    fn pgn_import_multiple_games() {
+        let pgn = "[Event \"One\"]\n1. d4 d5 *\n\n[Event \"Two\"]\n\
+                   [SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n\
+                   12... Kd7 13. e4 1/2-1/2\n\n[Event \"Three\"]\n[Result \"0-1\"]\n1. f3 e5";
+        let games = parse_pgn(pgn).unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[0].result, GameResult::Unfinished);
+        assert_eq!(games[1].result, GameResult::Draw);
+        assert_eq!(games[1].start_color, PieceColor::Black);
+        assert_eq!(
+            games[1].positions().unwrap()[2].to_fen(),
+            "8/3k4/8/8/4P3/8/8/4K3 b - e3 0 13"
+        );
+        // without a result token the Result tag is used
+        assert_eq!(games[2].result, GameResult::BlackWins);
+        assert_eq!(games[2].moves.len(), 2);
+    }
+
+    #[test]
+    fn pgn_import_errors() {
+        assert!(parse_pgn("1. e4 {never closed").is_err());
+        assert!(parse_pgn("1. e4 (1. d4 d5").is_err());
+        assert!(parse_pgn("1. e4 e5) *").is_err());
+        assert!(parse_pgn("[Event \"No end]").is_err());
+        assert!(parse_pgn("[FEN \"not a fen\"]\n*").is_err());
+
+        let games = parse_pgn("1. e4 e5 2. Ke3 *").unwrap();
+        assert!(games[0].positions().is_err());
+        assert!(parse_pgn("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn pgn_export_then_import() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut game = game_from(fen, &["O-O-O", "Bxe2", "Nxf7", "O-O", "Nh6+", "Kh8"]);
+        game.moves[1].eval = Some(-50);
+        game.set_result(GameResult::Unfinished, "Game stopped at the move limit");
+        let games = parse_pgn(&game.to_pgn(true)).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].tags, game.tags);
+        let sans = |game: &PgnGame| -> Vec<String> {
+            game.moves.iter().map(|mov| mov.san.clone()).collect()
+        };
+        assert_eq!(sans(&games[0]), sans(&game));
+        assert_eq!(games[0].positions().unwrap().len(), 7);
+    }
+}
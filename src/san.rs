@@ -0,0 +1,288 @@
+use crate::board::{PieceKind::*, *};
+use crate::move_generation::{generate_moves, is_check, MoveGenerationMode};
+use crate::zobrist::ZobristHasher;
+
+/*
+    Standard Algebraic Notation, the move format people and PGN files use (ex: e4, Nbd7, exd6, O-O, e8=Q+)
+    See https://en.wikipedia.org/wiki/Algebraic_notation_(chess)
+*/
+
+// Get the SAN letter for a piece, pawns do not have one
+fn piece_letter(kind: PieceKind) -> &'static str {
+    match kind {
+        Pawn => "",
+        Knight => "N",
+        Bishop => "B",
+        Rook => "R",
+        Queen => "Q",
+        King => "K",
+    }
+}
+
+fn piece_kind_from_letter(letter: char) -> Option<PieceKind> {
+    match letter {
+        'N' => Some(Knight),
+        'B' => Some(Bishop),
+        'R' => Some(Rook),
+        'Q' => Some(Queen),
+        'K' => Some(King),
+        _ => None,
+    }
+}
+
+fn piece_kind_at(board: &BoardState, point: Point) -> Option<PieceKind> {
+    match board.board[point.0][point.1] {
+        Square::Full(piece) => Some(piece.kind),
+        _ => None,
+    }
+}
+
+/*
+    Write a legal move in SAN, the file and/or rank the piece moved from is only
+    added when another piece of the same kind could move to the same square
+*/
+pub fn move_to_san(board: &BoardState, mov: Move, zobrist_hasher: &ZobristHasher) -> String {
+    let (start, end) = (mov.from(), mov.to());
+    let kind = piece_kind_at(board, start).unwrap_or(Pawn);
+    let start_alg = start.to_string();
+    let (start_file, start_rank) = start_alg.split_at(1);
+
+    let mut san = String::new();
+    if mov.is_castle() {
+        san.push_str(if end.1 > start.1 { "O-O" } else { "O-O-O" });
+    } else {
+        san.push_str(piece_letter(kind));
+        if kind == Pawn {
+            if mov.is_capture() {
+                san.push_str(start_file);
+            }
+        } else {
+            let others: Vec<Point> = generate_moves(board, MoveGenerationMode::AllMoves)
+                .into_iter()
+                .filter(|other| {
+                    other.to() == end
+                        && other.from() != start
+                        && piece_kind_at(board, other.from()) == Some(kind)
+                })
+                .map(|other| other.from())
+                .collect();
+            if !others.is_empty() {
+                if others.iter().all(|other| other.1 != start.1) {
+                    san.push_str(start_file);
+                } else if others.iter().all(|other| other.0 != start.0) {
+                    san.push_str(start_rank);
+                } else {
+                    san.push_str(&start_alg);
+                }
+            }
+        }
+        if mov.is_capture() {
+            san.push('x');
+        }
+        san.push_str(&end.to_string());
+        if let Some(promotion) = mov.promotion {
+            san.push('=');
+            san.push_str(piece_letter(promotion));
+        }
+    }
+
+    let mut after_move = board.clone();
+    after_move.make_move(mov, zobrist_hasher);
+    if is_check(&after_move, after_move.to_move) {
+        if generate_moves(&after_move, MoveGenerationMode::AllMoves).is_empty() {
+            san.push('#');
+        } else {
+            san.push('+');
+        }
+    }
+    san
+}
+
+/*
+    Find the legal move a SAN string describes, the check and mate suffixes, annotations like !? and
+    extra disambiguation are accepted, as are castles written with zeros and promotions without the =
+*/
+pub fn move_from_san(board: &BoardState, san: &str) -> Result<Move, &'static str> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal_moves = generate_moves(board, MoveGenerationMode::AllMoves);
+
+    if matches!(san, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+        let king_side = san.len() == 3;
+        return legal_moves
+            .into_iter()
+            .find(|mov| mov.is_castle() && (mov.to().1 > mov.from().1) == king_side)
+            .ok_or("Invalid move, not a legal move in this position");
+    }
+
+    let mut chars: Vec<char> = san.chars().collect();
+    let kind = match chars.first().copied().and_then(piece_kind_from_letter) {
+        Some(kind) => {
+            chars.remove(0);
+            kind
+        }
+        None => Pawn,
+    };
+
+    let mut promotion = None;
+    if kind == Pawn {
+        if let Some(promotion_kind) = chars.last().copied().and_then(piece_kind_from_letter) {
+            if promotion_kind == King {
+                return Err("Invalid move, can not promote to a king");
+            }
+            promotion = Some(promotion_kind);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return Err("Invalid move, could not parse SAN");
+    }
+    let end: Point = chars
+        .split_off(chars.len() - 2)
+        .into_iter()
+        .collect::<String>()
+        .parse()?;
+
+    let capture = chars.contains(&'x');
+    // whatever is left narrows down where the piece came from
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in chars.into_iter().filter(|&c| c != 'x') {
+        match c {
+            'a'..='h' if from_file.is_none() => from_file = Some(c as usize - 'a' as usize),
+            '1'..='8' if from_rank.is_none() => from_rank = Some(c as usize - '1' as usize),
+            _ => return Err("Invalid move, could not parse SAN"),
+        }
+    }
+
+    let mut candidates = legal_moves.into_iter().filter(|mov| {
+        let start = mov.from();
+        mov.to() == end
+            && !mov.is_castle()
+            && (mov.is_capture() || !capture)
+            && mov.promotion == promotion
+            && piece_kind_at(board, start) == Some(kind)
+            && from_file.is_none_or(|file| start.1 == file + BOARD_START)
+            && from_rank.is_none_or(|rank| start.0 == BOARD_END - 1 - rank)
+    });
+
+    match (candidates.next(), candidates.next()) {
+        (Some(mov), None) => Ok(mov),
+        (Some(_), Some(_)) => Err("Invalid move, more than one piece can make this move"),
+        (None, _) => Err("Invalid move, not a legal move in this position"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, long_algebraic: &str) -> String {
+        let board = BoardState::from_fen(fen).unwrap();
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        let mov = generate_moves(&board, MoveGenerationMode::AllMoves)
+            .into_iter()
+            .find(|mov| mov.to_string() == long_algebraic)
+            .unwrap();
+        move_to_san(&board, mov, &zobrist_hasher)
+    }
+
+    #[test]
+    fn san_simple_moves() {
+        assert_eq!(san(DEFAULT_FEN_STRING, "e2e4"), "e4");
+        assert_eq!(san(DEFAULT_FEN_STRING, "g1f3"), "Nf3");
+        let fen = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2";
+        assert_eq!(san(fen, "e4d5"), "exd5");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+    }
+
+    #[test]
+    fn san_disambiguation() {
+        // knights on b1 and f3 can both reach d2
+        let fen = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1d2"), "Nbd2");
+        // rooks on a1 and a5 can both reach a3
+        let fen = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a3"), "R1a3");
+        // queens on e4, h4 and h1 can all reach e1
+        let fen = "8/8/1k6/8/4Q2Q/8/8/K6Q w - - 0 1";
+        assert_eq!(san(fen, "h4e1"), "Qh4e1");
+        // the pinned knight can not move so there is nothing to disambiguate
+        let fen = "4k3/8/8/8/8/2N5/8/rN2K3 w - - 0 1";
+        assert_eq!(san(fen, "c3d1"), "Nd1");
+    }
+
+    #[test]
+    fn san_castling_promotion_check_and_mate() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        assert_eq!(san("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", "e1c1"), "O-O-O+");
+        let fen = "1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a7a8q"), "a8=Q");
+        assert_eq!(san("3k4/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), "a8=Q+");
+        assert_eq!(san(fen, "a7b8n"), "axb8=N");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/3QK3 w - - 0 1", "d1d8"), "Qd8#");
+    }
+
+    #[test]
+    fn san_parsing() {
+        let board = BoardState::from_fen("1r2k3/P7/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        for (san, long_algebraic) in [
+            ("O-O", "e1g1"),
+            ("0-0-0+", "e1c1"),
+            ("axb8=Q", "a7b8q"),
+            ("axb8N", "a7b8n"),
+            ("a8=R+", "a7a8r"),
+            ("Rh1h7!?", "h1h7"),
+            ("Kd1", "e1d1"),
+        ] {
+            assert_eq!(
+                move_from_san(&board, san).unwrap().to_string(),
+                long_algebraic
+            );
+        }
+
+        assert!(move_from_san(&board, "a8").is_err()); // missing the promotion
+        assert!(move_from_san(&board, "a8=K").is_err());
+        assert!(move_from_san(&board, "Ke3").is_err());
+        assert!(move_from_san(&board, "Nf3").is_err());
+        assert!(move_from_san(&board, "").is_err());
+        assert!(move_from_san(&board, "Rxz9").is_err());
+        assert!(move_from_san(&board, "Rxh7").is_err()); // nothing to capture
+
+        let board = BoardState::from_fen("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1").unwrap();
+        assert!(move_from_san(&board, "Nd2").is_err());
+        assert_eq!(move_from_san(&board, "Nfd2").unwrap().to_string(), "f3d2");
+    }
+
+    // every legal move should survive being written out and read back in
+    fn san_round_trip(board: &mut BoardState, depth: usize, zobrist_hasher: &ZobristHasher) {
+        if depth == 0 {
+            return;
+        }
+        for mov in generate_moves(board, MoveGenerationMode::AllMoves) {
+            let san = move_to_san(board, mov, zobrist_hasher);
+            assert_eq!(move_from_san(board, &san), Ok(mov), "{}", san);
+            let undo = board.make_move(mov, zobrist_hasher);
+            san_round_trip(board, depth - 1, zobrist_hasher);
+            board.unmake_move(mov, &undo);
+        }
+    }
+
+    #[test]
+    fn san_round_trip_perft_positions() {
+        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
+        for fen in [
+            DEFAULT_FEN_STRING,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ] {
+            san_round_trip(&mut BoardState::from_fen(fen).unwrap(), 2, &zobrist_hasher);
+        }
+    }
+}
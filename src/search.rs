@@ -1,59 +1,488 @@
 pub use crate::board::*;
+use crate::engine::MATE_SCORE;
+use crate::syzygy::Tablebases;
+use crate::transposition_table::Bound;
+use crate::utils::out_of_time;
+use std::cmp::min;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 pub const MAX_DEPTH: u8 = 100;
 pub const KILLER_MOVE_PLY_SIZE: usize = 2;
-type MoveArray = [Option<(Point, Point)>; MAX_DEPTH as usize];
-type KillerMoveArray =
-    [[Option<(Point, Point)>; KILLER_MOVE_PLY_SIZE]; MAX_DEPTH as usize];
+// threads add the nodes they searched to the shared count in batches of this size
+const NODE_FLUSH_INTERVAL: u64 = 1024;
+pub type MoveArray = [Option<Move>; MAX_DEPTH as usize];
+type KillerMoveArray = [[Option<Move>; KILLER_MOVE_PLY_SIZE]; MAX_DEPTH as usize];
+type HistoryTable = [[[i32; 64]; 64]; 2];
+type CountermoveTable = [[Option<Move>; 64]; 64];
+type PvTable = [MoveArray; MAX_DEPTH as usize];
+// history scores stay within -MAX_HISTORY..=MAX_HISTORY
+pub const MAX_HISTORY: i32 = 16384;
+
+/*
+    The conditions under which a search should end, the search ends as soon
+    as any one of these limits is reached
+*/
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,      // only search up to this many plies
+    pub nodes: Option<u64>,     // stop after searching this many nodes
+    pub movetime: Option<u128>, // stop after this many ms, either given directly or derived from the clock
+    pub mate: Option<u32>,      // stop once a mate in this many moves has been found
+    pub infinite: bool,         // keep searching until told to stop
+}
+
+/*
+    Flags shared between a running search and the thread that controls it
+*/
+#[derive(Default)]
+pub struct SearchSignals {
+    pub stop: AtomicBool,   // stop the search as soon as possible
+    pub ponder: AtomicBool, // searching on the opponents time, the move time does not apply yet
+}
+
+/*
+    The progress of a search, sent to the reporter every time the main thread finds a new
+    best line, and for every line at the end of an iteration when searching more than one
+*/
+#[derive(Clone, Debug)]
+pub struct SearchReport {
+    pub depth: u8,
+    pub multi_pv: Option<usize>, // the rank of this line when searching more than one
+    pub score: i32,              // from the point of view of the side to move
+    pub bound: Bound, // the score of a search that fell outside its aspiration window is only a bound
+    pub nodes: u64,
+    pub tb_hits: u64,
+    pub time_ms: u128,
+    pub pv: Vec<Move>,
+}
+
+// Receives the progress of a search as it happens, the UCI interface sends it on to the GUI
+pub type SearchReporter = Arc<dyn Fn(&SearchReport) + Send + Sync>;
+
+// The report as a UCI info line
+impl fmt::Display for SearchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "info depth {}", self.depth)?;
+        // in multi pv mode each line is tagged with its rank
+        if let Some(rank) = self.multi_pv {
+            write!(f, " multipv {}", rank)?;
+        }
+
+        let mate_window = 15;
+        if self.score >= MATE_SCORE - mate_window {
+            // this player is threatening checkmate
+            write!(f, " score mate {}", (MATE_SCORE - self.score + 1) / 2)?;
+        } else if self.score <= -MATE_SCORE + mate_window {
+            // this player is getting matted
+            write!(f, " score mate {}", (MATE_SCORE + self.score) / -2)?;
+        } else {
+            write!(f, " score cp {}", self.score)?;
+        }
+        match self.bound {
+            Bound::Exact => {}
+            Bound::Lower => write!(f, " lowerbound")?,
+            Bound::Upper => write!(f, " upperbound")?,
+        }
+        write!(
+            f,
+            " nodes {} tbhits {} time {}",
+            self.nodes, self.tb_hits, self.time_ms
+        )?;
+
+        // the pv runs to the end of the line, so it has to come last
+        write!(f, " pv")?;
+        for mov in &self.pv {
+            write!(f, " {}", mov)?;
+        }
+        Ok(())
+    }
+}
 
 /*
     Keep track of global information about the current search context
 */
-#[derive(Copy, Clone)]
 pub struct Search {
     pub killer_moves: KillerMoveArray, // the killer moves for this search
-    pub pv_moves: MoveArray,           // the principle variation for this search
-    pub cur_line: MoveArray,           // the current line being considered for this search
-    pub nodes_searched: u32,
+    pub history: HistoryTable, // how often a quiet move caused a cutoff, by color, from and to square
+    pub countermoves: CountermoveTable, // the quiet move that refuted a move last time, by its from and to square
+    pub pv_moves: MoveArray, // the principle variation of the best line so far, searched first
+    pub cur_line: MoveArray, // the moves played from the root to reach the node being searched
+    pv_table: PvTable,       // the best line found from each ply, see update_principle_variation
+    pv_length: [usize; MAX_DEPTH as usize], // where the line in each row of pv_table ends
+    pub nodes_searched: u64, // total nodes searched across all iterations
+    pub tb_hits: u64,        // positions found in the endgame tablebases
+    pub start: Instant,      // when our clock started for this search
+    pub limits: SearchLimits, // when the search should end
+    pub signals: Arc<SearchSignals>, // set from outside the search to control it
+    pub tablebases: Option<Arc<Tablebases>>, // probed for the result of positions with few pieces
+    pub reporter: Option<SearchReporter>, // told about the progress of the search, helpers have none
+    shared_nodes: Arc<AtomicU64>,         // nodes searched by every thread working on this search
+    unflushed_nodes: u64, // nodes searched by this thread not yet added to shared_nodes
+    pondering: bool,      // true until the opponent plays the move we are pondering on
 }
 
 impl Search {
-    pub fn new_search() -> Search {
+    pub fn new_search(start: Instant, limits: SearchLimits, signals: Arc<SearchSignals>) -> Search {
+        let pondering = signals.ponder.load(Ordering::Relaxed);
         Search {
             killer_moves: [[None; KILLER_MOVE_PLY_SIZE]; MAX_DEPTH as usize],
+            history: [[[0; 64]; 64]; 2],
+            countermoves: [[None; 64]; 64],
             pv_moves: [None; MAX_DEPTH as usize],
             cur_line: [None; MAX_DEPTH as usize],
+            pv_table: [[None; MAX_DEPTH as usize]; MAX_DEPTH as usize],
+            pv_length: [0; MAX_DEPTH as usize],
             nodes_searched: 0,
+            tb_hits: 0,
+            start,
+            limits,
+            signals,
+            tablebases: None,
+            reporter: None,
+            shared_nodes: Arc::new(AtomicU64::new(0)),
+            unflushed_nodes: 0,
+            pondering,
+        }
+    }
+
+    /*
+        Create the search context for a helper thread searching the same position, the helper
+        counts towards the same nodes but has no limits of its own, it runs until it is stopped
+    */
+    pub fn new_helper(&self, signals: Arc<SearchSignals>) -> Search {
+        let mut helper = Search::new_search(self.start, SearchLimits::default(), signals);
+        helper.tablebases = self.tablebases.clone();
+        helper.shared_nodes = self.shared_nodes.clone();
+        helper
+    }
+
+    // Check if the search should end, either because we hit one of the limits or were told to stop
+    pub fn should_stop(&mut self) -> bool {
+        if self.signals.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.pondering {
+            if self.signals.ponder.load(Ordering::Relaxed) {
+                // the opponent has not played the expected move yet, keep going
+                return false;
+            }
+            // the opponent played the ponder move, our clock starts now
+            self.pondering = false;
+            self.start = Instant::now();
+        }
+        if let Some(nodes) = self.limits.nodes {
+            if self.nodes() >= nodes {
+                return true;
+            }
+        }
+        match self.limits.movetime {
+            Some(time_to_move_ms) => out_of_time(self.start, time_to_move_ms),
+            None => false,
+        }
+    }
+
+    // Tell the reporter, if there is one, about a line found by the search
+    pub fn report(
+        &self,
+        pv: &MoveArray,
+        depth: u8,
+        score: i32,
+        multi_pv: Option<usize>,
+        bound: Bound,
+    ) {
+        if let Some(reporter) = &self.reporter {
+            reporter(&SearchReport {
+                depth,
+                multi_pv,
+                score,
+                bound,
+                nodes: self.nodes(),
+                tb_hits: self.tb_hits,
+                time_ms: Instant::now().duration_since(self.start).as_millis(),
+                pv: pv.iter().map_while(|&mov| mov).collect(),
+            });
         }
     }
 
     pub fn node_searched(&mut self) {
         self.nodes_searched += 1;
+        self.unflushed_nodes += 1;
+        if self.unflushed_nodes >= NODE_FLUSH_INTERVAL {
+            self.flush_nodes();
+        }
+    }
+
+    // Add the nodes this thread searched to the count shared with the other threads
+    pub fn flush_nodes(&mut self) {
+        self.shared_nodes
+            .fetch_add(self.unflushed_nodes, Ordering::Relaxed);
+        self.unflushed_nodes = 0;
     }
 
-    pub fn insert_killer_move(&mut self, ply_from_root: i32, mov: &BoardState) {
+    // Nodes searched by all threads so far, the other threads report theirs in batches
+    pub fn nodes(&self) -> u64 {
+        self.shared_nodes.load(Ordering::Relaxed) + self.unflushed_nodes
+    }
+
+    pub fn insert_killer_move(&mut self, ply_from_root: i32, mov: Move) {
         let ply = ply_from_root as usize;
-        if self.killer_moves[ply].contains(&mov.last_move) {
+        if self.killer_moves[ply].contains(&Some(mov)) {
             return;
         }
 
         for i in 0..(KILLER_MOVE_PLY_SIZE - 1) {
             self.killer_moves[ply][i + 1] = self.killer_moves[ply][i];
         }
-        self.killer_moves[ply][0] = mov.last_move;
+        self.killer_moves[ply][0] = Some(mov);
+    }
+
+    /*
+        A quiet move caused a beta cutoff, remember it as a killer move, a countermove to the move
+        that led here and raise its history score. The quiet moves searched before it failed to
+        cause a cutoff, so their history scores are lowered
+        https://www.chessprogramming.org/History_Heuristic
+        https://www.chessprogramming.org/Countermove_Heuristic
+    */
+    pub fn quiet_cutoff(
+        &mut self,
+        color: PieceColor,
+        ply_from_root: i32,
+        depth: u8,
+        mov: Move,
+        failed_quiets: &[Move],
+    ) {
+        self.insert_killer_move(ply_from_root, mov);
+        if let Some(previous) = self.previous_move(ply_from_root) {
+            self.countermoves[previous.from_square()][previous.to_square()] = Some(mov);
+        }
+
+        let bonus = min(depth as i32 * depth as i32, MAX_HISTORY);
+        self.update_history(color, mov, bonus);
+        for &failed in failed_quiets {
+            self.update_history(color, failed, -bonus);
+        }
+    }
+
+    /*
+        History gravity, the closer a score is to the limit the less it moves in that direction
+        so moves that stop causing cutoffs lose their place quickly
+    */
+    fn update_history(&mut self, color: PieceColor, mov: Move, bonus: i32) {
+        let entry = &mut self.history[color.index()][mov.from_square()][mov.to_square()];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
+
+    pub fn history_score(&self, color: PieceColor, mov: Move) -> i32 {
+        self.history[color.index()][mov.from_square()][mov.to_square()]
+    }
+
+    // The quiet move that refuted the move played to reach this ply, if any
+    pub fn countermove(&self, ply_from_root: i32) -> Option<Move> {
+        let previous = self.previous_move(ply_from_root)?;
+        self.countermoves[previous.from_square()][previous.to_square()]
+    }
+
+    fn previous_move(&self, ply_from_root: i32) -> Option<Move> {
+        match ply_from_root {
+            0 => None,
+            ply => self.cur_line[ply as usize - 1],
+        }
+    }
+
+    // Scores from earlier iterations were found with shallower searches, count them for less
+    pub fn age_history(&mut self) {
+        self.history
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|score| *score /= 2);
+    }
+
+    pub fn insert_into_cur_line(&mut self, ply_from_root: i32, mov: Move) {
+        self.cur_line[ply_from_root as usize] = Some(mov);
+    }
+
+    // A node at this ply is being searched, no line has been found from it yet
+    pub fn clear_principle_variation(&mut self, ply_from_root: i32) {
+        self.pv_length[ply_from_root as usize] = ply_from_root as usize;
+    }
+
+    /*
+        Triangular PV table https://www.chessprogramming.org/Triangular_PV-Table
+
+        Row ply of the table holds the best line found from the node being searched at that ply,
+        starting at index ply. When a move becomes the best move at a ply, the line from there is
+        that move followed by the line from the node it led to, which has just been searched. This
+        way the line is always made of moves that follow one another, unlike a single line that
+        is shared by every node
+    */
+    pub fn update_principle_variation(&mut self, ply_from_root: i32, mov: Move) {
+        let ply = ply_from_root as usize;
+        let end = match ply + 1 {
+            child if child < MAX_DEPTH as usize => self.pv_length[child].max(child),
+            child => child,
+        };
+        let (rows, child_rows) = self.pv_table.split_at_mut(ply + 1);
+        rows[ply][ply] = Some(mov);
+        if end > ply + 1 {
+            rows[ply][ply + 1..end].copy_from_slice(&child_rows[0][ply + 1..end]);
+        }
+        self.pv_length[ply] = end;
     }
 
-    pub fn insert_into_cur_line(&mut self, ply_from_root: i32, mov: &BoardState) {
-        self.cur_line[ply_from_root as usize] = mov.last_move;
+    /*
+        The move to search first when following the principal variation, only nodes reached by
+        playing the principal variation up to this ply are on it
+    */
+    pub fn pv_move(&self, ply_from_root: i32) -> Option<Move> {
+        let ply = ply_from_root as usize;
+        if self.cur_line[..ply] == self.pv_moves[..ply] {
+            self.pv_moves[ply]
+        } else {
+            None
+        }
     }
 
-    pub fn set_principle_variation(&mut self) {
-        self.pv_moves.clone_from_slice(&self.cur_line);
+    // The best line found from the root
+    pub fn principle_variation(&self) -> MoveArray {
+        let mut pv = [None; MAX_DEPTH as usize];
+        let length = self.pv_length[0];
+        pv[..length].copy_from_slice(&self.pv_table[0][..length]);
+        pv
     }
 
     // reset the required data to search the next depth
     pub fn reset_search(&mut self) {
-        self.nodes_searched = 0;
         self.cur_line = [None; MAX_DEPTH as usize];
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search() -> Search {
+        Search::new_search(
+            Instant::now(),
+            SearchLimits::default(),
+            Arc::new(SearchSignals::default()),
+        )
+    }
+
+    const E4: Move = Move::new(Point(8, 6), Point(6, 6), None, Move::DOUBLE_PAWN_PUSH);
+    const D4: Move = Move::new(Point(8, 5), Point(6, 5), None, Move::DOUBLE_PAWN_PUSH);
+    const E5: Move = Move::new(Point(3, 6), Point(5, 6), None, Move::DOUBLE_PAWN_PUSH);
+
+    #[test]
+    fn quiet_cutoff_updates_history() {
+        let mut search = search();
+        search.quiet_cutoff(PieceColor::White, 0, 4, E4, &[D4]);
+        assert_eq!(search.history_score(PieceColor::White, E4), 16);
+        assert_eq!(search.history_score(PieceColor::White, D4), -16);
+        // every side has its own history
+        assert_eq!(search.history_score(PieceColor::Black, E4), 0);
+        assert_eq!(search.killer_moves[0][0], Some(E4));
+
+        search.age_history();
+        assert_eq!(search.history_score(PieceColor::White, E4), 8);
+        assert_eq!(search.history_score(PieceColor::White, D4), -8);
+    }
+
+    #[test]
+    fn history_stays_within_bounds() {
+        let mut search = search();
+        for _ in 0..1000 {
+            search.quiet_cutoff(PieceColor::White, 0, 50, E4, &[D4]);
+        }
+        assert!(search.history_score(PieceColor::White, E4) <= MAX_HISTORY);
+        assert!(search.history_score(PieceColor::White, D4) >= -MAX_HISTORY);
+        assert!(search.history_score(PieceColor::White, E4) > MAX_HISTORY / 2);
+    }
+
+    #[test]
+    fn countermove_remembered_for_previous_move() {
+        let mut search = search();
+        assert_eq!(search.countermove(1), None);
+        search.insert_into_cur_line(0, E4);
+        search.quiet_cutoff(PieceColor::Black, 1, 3, E5, &[]);
+        assert_eq!(search.countermove(1), Some(E5));
+        // there is no previous move at the root
+        assert_eq!(search.countermove(0), None);
+
+        search.insert_into_cur_line(0, D4);
+        assert_eq!(search.countermove(1), None);
+    }
+
+    #[test]
+    fn principle_variation_built_from_child_lines() {
+        let mut search = search();
+        search.clear_principle_variation(0);
+        search.clear_principle_variation(1);
+        search.clear_principle_variation(2);
+        search.update_principle_variation(2, D4);
+        search.update_principle_variation(1, E5);
+        search.update_principle_variation(0, E4);
+        assert_eq!(
+            search.principle_variation()[..4],
+            [Some(E4), Some(E5), Some(D4), None]
+        );
+
+        // a new best move at the root whose reply ended the search there leaves only that move
+        search.clear_principle_variation(1);
+        search.update_principle_variation(0, D4);
+        assert_eq!(search.principle_variation()[..2], [Some(D4), None]);
+    }
+
+    #[test]
+    fn pv_move_only_on_the_principle_variation() {
+        let mut search = search();
+        search.pv_moves[..2].copy_from_slice(&[Some(E4), Some(E5)]);
+        assert_eq!(search.pv_move(0), Some(E4));
+        search.insert_into_cur_line(0, E4);
+        assert_eq!(search.pv_move(1), Some(E5));
+        search.insert_into_cur_line(0, D4);
+        assert_eq!(search.pv_move(1), None);
+    }
+
+    #[test]
+    fn report_formatted_as_uci_info_line() {
+        let mut report = SearchReport {
+            depth: 6,
+            multi_pv: None,
+            score: 31,
+            bound: Bound::Exact,
+            nodes: 12345,
+            tb_hits: 0,
+            time_ms: 42,
+            pv: vec![E4, E5, D4],
+        };
+        assert_eq!(
+            report.to_string(),
+            "info depth 6 score cp 31 nodes 12345 tbhits 0 time 42 pv e2e4 e7e5 d2d4"
+        );
+
+        report.multi_pv = Some(2);
+        report.bound = Bound::Lower;
+        assert_eq!(
+            report.to_string(),
+            "info depth 6 multipv 2 score cp 31 lowerbound nodes 12345 tbhits 0 time 42 pv e2e4 e7e5 d2d4"
+        );
+
+        report.multi_pv = None;
+        report.bound = Bound::Exact;
+        report.score = MATE_SCORE - 3;
+        report.pv.truncate(1);
+        assert_eq!(
+            report.to_string(),
+            "info depth 6 score mate 2 nodes 12345 tbhits 0 time 42 pv e2e4"
+        );
+        report.score = -MATE_SCORE + 2;
+        assert_eq!(
+            report.to_string(),
+            "info depth 6 score mate -1 nodes 12345 tbhits 0 time 42 pv e2e4"
+        );
+    }
+}
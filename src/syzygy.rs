@@ -0,0 +1,1567 @@
+use crate::bitboard::{self, bit};
+use crate::board::{PieceColor::*, PieceKind::*, *};
+use crate::move_generation::{generate_moves, is_check, MoveGenerationMode};
+use crate::zobrist::ZobristHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::ops::Neg;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/*
+    Probing of Syzygy endgame tablebases, see https://syzygy-tables.info
+
+    Each material combination has two files, the WDL table (.rtbw) stores if a position is won,
+    drawn or lost and the DTZ table (.rtbz) stores how many plies it takes until the next capture
+    or pawn move (distance to zero) while playing the fastest win. Only positions without castling
+    rights can be probed.
+
+    Files are only read the first time a table is probed. The decoding follows the reference
+    probing code written by Ronald de Man, the author of the tables, squares in here are
+    numbered the way the tables do it, from a1 (0) to h8 (63)
+*/
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+const TB_PIECES: usize = 7;
+
+// flags stored for every sub table
+const FLAG_STM: u8 = 1; // the DTZ table is for black to move
+const FLAG_MAPPED: u8 = 2; // DTZ values are stored through a map
+const FLAG_WIN_PLIES: u8 = 4; // winning DTZ values are in plies rather than moves
+const FLAG_LOSS_PLIES: u8 = 8; // losing DTZ values are in plies rather than moves
+const FLAG_WIDE: u8 = 16; // the DTZ map has 16 bit entries
+const FLAG_SINGLE_VALUE: u8 = 128; // every position in the table has the same value
+
+// marks a leaf in the symbol tree
+const LEAF: usize = 0xfff;
+
+/*
+    The result of a position assuming perfect play, a cursed win is a win that
+    takes too long to convert under the fifty move rule, a blessed loss is the
+    same from the losing side
+*/
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Wdl> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32)).unwrap()
+    }
+}
+
+// The DTZ of a position where the best move is a capture or pawn move
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/*
+    Rank the DTZ of a root move, higher is better
+
+    Wins that can be converted before the fifty move rule kicks in are all ranked the
+    same, as are losses the opponent can convert, anything else is ranked by how close
+    it gets to the fifty move rule
+*/
+pub const MAX_DTZ_RANK: i32 = 1000;
+
+pub fn dtz_rank(dtz: i32, half_move_clock: u16) -> i32 {
+    let half_move_clock = half_move_clock as i32;
+    if dtz > 0 {
+        if dtz + half_move_clock <= 99 {
+            MAX_DTZ_RANK
+        } else {
+            MAX_DTZ_RANK - (dtz + half_move_clock)
+        }
+    } else if dtz < 0 {
+        if -dtz * 2 + half_move_clock < 100 {
+            -MAX_DTZ_RANK
+        } else {
+            -MAX_DTZ_RANK + (-dtz + half_move_clock)
+        }
+    } else {
+        0
+    }
+}
+
+/*
+    Tables used to turn a position into an index, computed once
+*/
+struct Encoding {
+    map_b1h1h7: [u64; 64],    // squares below the a1-h8 diagonal to 0..27
+    map_a1d1d4: [usize; 64],  // squares in the a1-d1-d4 triangle to 0..9
+    map_kk: [[u64; 64]; 10],  // the 462 legal ways to place both kings
+    binomial: [[u64; 64]; 7], // binomial[k][n] ways to pick k of n squares
+    map_pawns: [usize; 64],   // pawn squares to 0..47, the highest is the leading pawn
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+// Positive above the a1-h8 diagonal, negative below
+fn off_a1h8(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut encoding = Encoding {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 7],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_a1h8(square) < 0 {
+                encoding.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        // squares on the diagonal are encoded last
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for square in 0..=27 {
+            if off_a1h8(square) < 0 && file_of(square) <= 3 {
+                encoding.map_a1d1d4[square] = code;
+                code += 1;
+            } else if off_a1h8(square) == 0 && file_of(square) <= 3 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            encoding.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // with the first king on the diagonal the second one can not be above it,
+        // positions with both kings on the diagonal are encoded last
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            for first in 0..=27 {
+                // b1 is mapped to 0, every square outside of the triangle is as well
+                if encoding.map_a1d1d4[first] != idx || (idx == 0 && first != 1) {
+                    continue;
+                }
+                for second in 0..64 {
+                    let touching = file_of(first).abs_diff(file_of(second)) <= 1
+                        && rank_of(first).abs_diff(rank_of(second)) <= 1;
+                    if touching || (off_a1h8(first) == 0 && off_a1h8(second) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(first) == 0 && off_a1h8(second) == 0 {
+                        both_on_diagonal.push((idx, second));
+                    } else {
+                        encoding.map_kk[idx][second] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            encoding.map_kk[idx][second] = code;
+            code += 1;
+        }
+
+        encoding.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7 {
+                if k > n {
+                    break;
+                }
+                let with = if k > 0 {
+                    encoding.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n {
+                    encoding.binomial[k][n - 1]
+                } else {
+                    0
+                };
+                encoding.binomial[k][n] = with + without;
+            }
+        }
+
+        // the leading pawn is the one closest to the a or h file, with the lowest rank
+        // after that, there are fewer squares left for the other pawns the further up it is
+        let mut available_squares = 48;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        encoding.map_pawns[square] = available_squares - 1;
+                        encoding.map_pawns[square ^ 7] = available_squares - 2;
+                        available_squares -= 2;
+                    }
+                    encoding.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += encoding.binomial[lead_pawns - 1][encoding.map_pawns[square]];
+                }
+                encoding.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        encoding
+    })
+}
+
+/*
+    A compressed table, tables with pawns have one per file the leading pawn
+    can be on (a to d) and WDL tables have one for each side to move
+*/
+#[derive(Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; TB_PIECES], // the order pieces are encoded in
+    group_len: [usize; TB_PIECES + 1],
+    group_idx: [u64; TB_PIECES + 1],
+    block_size: u64,
+    span: u64, // how many values each sparse index entry covers
+    num_blocks: u64,
+    min_sym_len: u8, // the stored value for single value tables
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>, // how many values each symbol expands to, minus one
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: u64,
+    block_lengths: usize,
+    block_lengths_size: u64,
+    data: usize,
+    map_idx: [usize; 4], // where the DTZ values for each WDL result start in the map
+}
+
+/*
+    A table file read into memory, the positions in PairsData are offsets into bytes
+*/
+struct TableData {
+    bytes: Vec<u8>,
+    pairs: Vec<PairsData>,
+    sides: usize,
+    map: usize,
+}
+
+fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// The compressed data is read as big endian, anything past the end of the file is treated as zeros
+fn read_u32_be(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    for (i, byte) in word.iter_mut().enumerate() {
+        *byte = bytes.get(offset + i).copied().unwrap_or(0);
+    }
+    u32::from_be_bytes(word)
+}
+
+impl TableData {
+    fn pairs(&self, stm: usize, file: usize) -> &PairsData {
+        &self.pairs[file * self.sides + stm % self.sides]
+    }
+
+    // The two children of a symbol in the symbol tree
+    fn children(&self, d: &PairsData, sym: usize) -> Option<(usize, usize)> {
+        let lr = self.bytes.get(d.btree + 3 * sym..d.btree + 3 * sym + 3)?;
+        let left = ((lr[1] as usize & 0xf) << 8) | lr[0] as usize;
+        let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+        Some((left, right))
+    }
+
+    /*
+        Parse the header of a table, the header has the piece order and the Huffman
+        code of every sub table followed by the indexes into the compressed blocks
+    */
+    fn parse(bytes: Vec<u8>, info: &TableInfo, dtz: bool) -> Option<TableData> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.get(0..4)? != magic {
+            return None;
+        }
+        let split = info.key != info.key2;
+        let flags = read_u8(&bytes, 4)?;
+        if (flags & 1 != 0) != split || (flags & 2 != 0) != info.has_pawns {
+            return None;
+        }
+
+        let sides = if !dtz && split { 2 } else { 1 };
+        let files = if info.has_pawns { 4 } else { 1 };
+        let pawns_on_both_sides = info.has_pawns && info.pawn_count[1] > 0;
+        let mut table = TableData {
+            bytes,
+            pairs: Vec::new(),
+            sides,
+            map: 0,
+        };
+        let bytes = &table.bytes;
+
+        let mut offset = 5;
+        for file in 0..files {
+            let order_byte = read_u8(bytes, offset)?;
+            let second_order_byte = if pawns_on_both_sides {
+                read_u8(bytes, offset + 1)?
+            } else {
+                0xff
+            };
+            let order = [
+                [order_byte & 0xf, second_order_byte & 0xf],
+                [order_byte >> 4, second_order_byte >> 4],
+            ];
+            offset += 1 + pawns_on_both_sides as usize;
+
+            let mut file_pairs: Vec<PairsData> = (0..sides).map(|_| PairsData::default()).collect();
+            for k in 0..info.piece_count {
+                let byte = read_u8(bytes, offset)?;
+                for (side, d) in file_pairs.iter_mut().enumerate() {
+                    d.pieces[k] = if side == 0 { byte & 0xf } else { byte >> 4 };
+                }
+                offset += 1;
+            }
+            for (side, d) in file_pairs.iter_mut().enumerate() {
+                set_groups(info, d, order[side], file);
+            }
+            table.pairs.extend(file_pairs);
+        }
+
+        offset += offset & 1;
+        for d in table.pairs.iter_mut() {
+            offset = set_sizes(bytes, d, offset)?;
+        }
+
+        if dtz {
+            table.map = offset;
+            for file in 0..files {
+                let d = &mut table.pairs[file];
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    offset += offset & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (offset - table.map) / 2 + 1;
+                        offset += 2 * read_u16(bytes, offset)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = offset - table.map + 1;
+                        offset += read_u8(bytes, offset)? as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        for d in table.pairs.iter_mut() {
+            d.sparse_index = offset;
+            offset += d.sparse_index_size as usize * 6;
+        }
+        for d in table.pairs.iter_mut() {
+            d.block_lengths = offset;
+            offset += d.block_lengths_size as usize * 2;
+        }
+        for d in table.pairs.iter_mut() {
+            offset = (offset + 0x3f) & !0x3f;
+            d.data = offset;
+            offset += (d.num_blocks * d.block_size) as usize;
+            if d.num_blocks > 0 && offset > table.bytes.len() {
+                return None;
+            }
+        }
+        Some(table)
+    }
+
+    /*
+        Find the value stored at an index, the values are compressed with a canonical
+        Huffman code over symbols that each expand into one or more values
+    */
+    fn decompress_pairs(&self, d: &PairsData, idx: u64) -> Option<i32> {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(d.min_sym_len as i32);
+        }
+        let bytes = &self.bytes;
+        let block_length = |block: u64| -> Option<i64> {
+            Some(read_u16(bytes, d.block_lengths + 2 * block as usize)? as i64)
+        };
+
+        // the sparse index points to a block and an offset for every span values,
+        // walk from there to the block that holds our value
+        let entry = d.sparse_index + 6 * (idx / d.span) as usize;
+        let mut block = read_u32(bytes, entry)? as u64;
+        let mut offset = read_u16(bytes, entry + 4)? as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        let mut ptr = d.data + (block * d.block_size) as usize;
+        let mut buf64 =
+            ((read_u32_be(bytes, ptr) as u64) << 32) | read_u32_be(bytes, ptr + 4) as u64;
+        ptr += 8;
+        let mut buf64_size = 64;
+        let min_sym_len = d.min_sym_len as u32;
+        let mut sym = loop {
+            // symbols of every length are consecutive, find the length then the symbol
+            let mut len = 0;
+            while buf64 < *d.base64.get(len)? {
+                len += 1;
+            }
+            let shift = 64 - len as u32 - min_sym_len;
+            let code = (buf64 - d.base64[len]).checked_shr(shift).unwrap_or(0) as usize;
+            let sym = code + read_u16(bytes, d.lowest_sym + 2 * len)? as usize;
+            let sym_len = *d.symlen.get(sym)? as i64;
+            if offset < sym_len + 1 {
+                break sym;
+            }
+            offset -= sym_len + 1;
+            let len = len as u32 + min_sym_len;
+            buf64 = buf64.checked_shl(len).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (read_u32_be(bytes, ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        };
+
+        // expand the symbol until we reach the single value we are after
+        while d.symlen[sym] != 0 {
+            let (left, right) = self.children(d, sym)?;
+            let left_len = *d.symlen.get(left)? as i64;
+            if offset < left_len + 1 {
+                sym = left;
+            } else {
+                offset -= left_len + 1;
+                sym = right;
+            }
+            d.symlen.get(sym)?;
+        }
+        Some(self.children(d, sym)?.0 as i32)
+    }
+}
+
+/*
+    Split the pieces into groups that are encoded together, the leading group has the kings
+    (and a third piece when there is a piece only one side has one of) or the leading pawns
+*/
+fn set_groups(info: &TableInfo, d: &mut PairsData, order: [u8; 2], file: usize) {
+    let encoding = encoding();
+    let mut n = 0;
+    let mut first_len: i32 = if info.has_pawns {
+        0
+    } else if info.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    d.group_len[0] = 1;
+    for i in 1..info.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    // the order groups are multiplied together in is stored in the table
+    let pawns_on_both_sides = info.has_pawns && info.pawn_count[1] > 0;
+    let mut next = if pawns_on_both_sides { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0];
+    if pawns_on_both_sides {
+        free_squares -= d.group_len[1];
+    }
+    let mut idx = 1;
+    let mut k = 0;
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            d.group_idx[0] = idx;
+            idx *= if info.has_pawns {
+                encoding.lead_pawns_size[d.group_len[0]][file]
+            } else if info.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] as usize {
+            d.group_idx[1] = idx;
+            idx *= encoding.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= encoding.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+/*
+    Read the sizes and the Huffman code of a sub table, returns where the next one starts
+*/
+fn set_sizes(bytes: &[u8], d: &mut PairsData, mut offset: usize) -> Option<usize> {
+    d.flags = read_u8(bytes, offset)?;
+    offset += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = read_u8(bytes, offset)?;
+        return Some(offset + 1);
+    }
+
+    let groups = d.group_len.iter().position(|&len| len == 0)?;
+    let table_size = d.group_idx[groups];
+    d.block_size = 1 << read_u8(bytes, offset)?;
+    d.span = 1 << read_u8(bytes, offset + 1)?;
+    d.sparse_index_size = table_size.div_ceil(d.span);
+    let padding = read_u8(bytes, offset + 2)? as u64;
+    d.num_blocks = read_u32(bytes, offset + 3)? as u64;
+    d.block_lengths_size = d.num_blocks + padding;
+    let max_sym_len = read_u8(bytes, offset + 7)?;
+    d.min_sym_len = read_u8(bytes, offset + 8)?;
+    if max_sym_len < d.min_sym_len || max_sym_len > 64 {
+        return None;
+    }
+    d.lowest_sym = offset + 9;
+
+    // https://en.wikipedia.org/wiki/Canonical_Huffman_code, base64[i] is the lowest code of
+    // length min_sym_len + i, left aligned in 64 bits so codes can be compared directly
+    let lengths = (max_sym_len - d.min_sym_len) as usize + 1;
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let lowest = read_u16(bytes, d.lowest_sym + 2 * i)? as u64;
+        let next_lowest = read_u16(bytes, d.lowest_sym + 2 * (i + 1))? as u64;
+        d.base64[i] = (d.base64[i + 1] + lowest).wrapping_sub(next_lowest) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base = base
+            .checked_shl(64 - i as u32 - d.min_sym_len as u32)
+            .unwrap_or(0);
+    }
+    offset = d.lowest_sym + 2 * lengths;
+
+    let symbols = read_u16(bytes, offset)? as usize;
+    offset += 2;
+    d.btree = offset;
+    let tree = bytes.get(offset..offset + 3 * symbols)?;
+    let children: Vec<(usize, usize)> = tree
+        .chunks_exact(3)
+        .map(|lr| {
+            let left = ((lr[1] as usize & 0xf) << 8) | lr[0] as usize;
+            let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+            (left, right)
+        })
+        .collect();
+    if children
+        .iter()
+        .any(|&(left, right)| right != LEAF && (left >= symbols || right >= symbols))
+    {
+        return None;
+    }
+
+    // symbols are built by pairing up two smaller symbols, a symbol always comes after its children
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(&children, &mut d.symlen, &mut visited, sym);
+        }
+    }
+    Some(offset + 3 * symbols + (symbols & 1))
+}
+
+fn set_symlen(
+    children: &[(usize, usize)],
+    symlen: &mut [u8],
+    visited: &mut [bool],
+    sym: usize,
+) -> u8 {
+    visited[sym] = true;
+    let (left, right) = children[sym];
+    if right == LEAF {
+        return 0;
+    }
+    if !visited[left] {
+        symlen[left] = set_symlen(children, symlen, visited, left);
+    }
+    if !visited[right] {
+        symlen[right] = set_symlen(children, symlen, visited, right);
+    }
+    symlen[left].wrapping_add(symlen[right]).wrapping_add(1)
+}
+
+/*
+    A material combination we have files for, named by the pieces each side has (ex: KRPvKR)
+    with white on the left. The same table is used when the colors are reversed
+*/
+struct TableInfo {
+    key: String,
+    key2: String, // the name with the colors reversed
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool, // some piece other than a king only one side has one of
+    pawn_count: [usize; 2],  // the side with the leading pawns first
+    wdl_path: Option<PathBuf>,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>,
+}
+
+const PIECE_ORDER: [PieceKind; 6] = [King, Queen, Rook, Bishop, Knight, Pawn];
+
+fn piece_letter(kind: PieceKind) -> char {
+    match kind {
+        King => 'K',
+        Queen => 'Q',
+        Rook => 'R',
+        Bishop => 'B',
+        Knight => 'N',
+        Pawn => 'P',
+    }
+}
+
+fn side_name(counts: &[usize; 6]) -> String {
+    PIECE_ORDER
+        .iter()
+        .flat_map(|&kind| std::iter::repeat_n(piece_letter(kind), counts[kind.index()]))
+        .collect()
+}
+
+// Count the pieces of one side of a table name (ex: KRP), each side must have exactly one king
+fn parse_side(name: &str) -> Option<[usize; 6]> {
+    let mut counts = [0; 6];
+    for letter in name.chars() {
+        let kind = PIECE_ORDER
+            .iter()
+            .find(|&&kind| piece_letter(kind) == letter)?;
+        counts[kind.index()] += 1;
+    }
+    if counts[King.index()] != 1 || side_name(&counts) != name {
+        return None;
+    }
+    Some(counts)
+}
+
+impl TableInfo {
+    fn new(name: &str) -> Option<TableInfo> {
+        let (white, black) = name.split_once('v')?;
+        let counts = [parse_side(white)?, parse_side(black)?];
+        let pawns = [counts[0][Pawn.index()], counts[1][Pawn.index()]];
+        // the side with fewer pawns leads as it compresses better
+        let white_leads = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+        Some(TableInfo {
+            key: name.to_string(),
+            key2: format!("{}v{}", black, white),
+            piece_count: counts.iter().flatten().sum(),
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: counts
+                .iter()
+                .any(|side| PIECE_ORDER[1..].iter().any(|kind| side[kind.index()] == 1)),
+            pawn_count: if white_leads {
+                [pawns[0], pawns[1]]
+            } else {
+                [pawns[1], pawns[0]]
+            },
+            wdl_path: None,
+            dtz_path: None,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn load(&self, dtz: bool) -> Option<&TableData> {
+        let (path, table) = if dtz {
+            (&self.dtz_path, &self.dtz)
+        } else {
+            (&self.wdl_path, &self.wdl)
+        };
+        table
+            .get_or_init(|| {
+                let bytes = fs::read(path.as_ref()?).ok()?;
+                TableData::parse(bytes, self, dtz)
+            })
+            .as_ref()
+    }
+}
+
+// A table piece code, white pieces are 1 (pawn) to 6 (king) and black pieces are the same plus 8
+fn tb_piece(color: PieceColor, kind: PieceKind) -> u8 {
+    let code = match kind {
+        Pawn => 1,
+        Knight => 2,
+        Bishop => 3,
+        Rook => 4,
+        Queen => 5,
+        King => 6,
+    };
+    match color {
+        White => code,
+        Black => code + 8,
+    }
+}
+
+// Our squares go from a8 (0) to h1 (63), the tables go from a1 (0) to h8 (63)
+fn tb_square(square: usize) -> usize {
+    square ^ 56
+}
+
+fn piece_count(board: &BoardState) -> usize {
+    (board.occupied[White.index()] | board.occupied[Black.index()]).count_ones() as usize
+}
+
+fn has_castling_rights(board: &BoardState) -> bool {
+    board.white_king_side_castle
+        || board.white_queen_side_castle
+        || board.black_king_side_castle
+        || board.black_queen_side_castle
+}
+
+// The name of the table for this position, with white on the left
+fn material_name(board: &BoardState) -> String {
+    let counts = |color: PieceColor| {
+        let mut counts = [0; 6];
+        for kind in PIECE_ORDER {
+            counts[kind.index()] = board.pieces[color.index()][kind.index()].count_ones() as usize;
+        }
+        counts
+    };
+    format!(
+        "{}v{}",
+        side_name(&counts(White)),
+        side_name(&counts(Black))
+    )
+}
+
+fn is_pawn_move(board: &BoardState, mov: Move) -> bool {
+    board.pieces[board.to_move.index()][Pawn.index()] & bit(mov.from()) != 0
+}
+
+// DTZ tables only store one side to move, the other side needs a one ply search
+enum TableValue {
+    Value(i32),
+    OtherSideToMove,
+}
+
+pub struct Tablebases {
+    tables: Vec<TableInfo>,
+    by_material: HashMap<String, usize>, // both names of every table
+    max_pieces: usize,
+    zobrist_hasher: ZobristHasher,
+}
+
+impl Tablebases {
+    /*
+        Find the tables in a list of directories, separated by : (or ; on Windows)
+        the same way the PATH environment variable is
+    */
+    pub fn open(path: &str) -> Result<Tablebases, &'static str> {
+        let mut tablebases = Tablebases {
+            tables: Vec::new(),
+            by_material: HashMap::new(),
+            max_pieces: 0,
+            zobrist_hasher: ZobristHasher::create_zobrist_hasher(),
+        };
+        for directory in env::split_paths(path) {
+            let entries =
+                fs::read_dir(&directory).map_err(|_| "Could not read Syzygy directory")?;
+            for entry in entries.flatten() {
+                let file = entry.path();
+                let (Some(name), Some(extension)) = (
+                    file.file_stem().and_then(|stem| stem.to_str()),
+                    file.extension().and_then(|extension| extension.to_str()),
+                ) else {
+                    continue;
+                };
+                if extension != "rtbw" && extension != "rtbz" {
+                    continue;
+                }
+                let index = match tablebases.by_material.get(name) {
+                    Some(&index) => index,
+                    None => {
+                        let Some(info) = TableInfo::new(name) else {
+                            continue;
+                        };
+                        // a table found in two directories is only added once
+                        if tablebases.by_material.contains_key(&info.key2) {
+                            continue;
+                        }
+                        tablebases.max_pieces = tablebases.max_pieces.max(info.piece_count);
+                        tablebases
+                            .by_material
+                            .insert(info.key.clone(), tablebases.tables.len());
+                        tablebases
+                            .by_material
+                            .insert(info.key2.clone(), tablebases.tables.len());
+                        tablebases.tables.push(info);
+                        tablebases.tables.len() - 1
+                    }
+                };
+                let info = &mut tablebases.tables[index];
+                let table_path = if extension == "rtbw" {
+                    &mut info.wdl_path
+                } else {
+                    &mut info.dtz_path
+                };
+                table_path.get_or_insert(file);
+            }
+        }
+        Ok(tablebases)
+    }
+
+    // How many material combinations there are tables for
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    // The most pieces, kings included, of any table
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Check if a position has few enough pieces to be probed and no castling rights
+    pub fn can_probe(&self, board: &BoardState) -> bool {
+        piece_count(board) <= self.max_pieces && !has_castling_rights(board)
+    }
+
+    /*
+        Look up the result of a position, returns None when the position is
+        not covered by the tables or the table could not be read
+    */
+    pub fn probe_wdl(&self, board: &BoardState) -> Option<Wdl> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        Some(self.search(&mut board.clone(), false)?.0)
+    }
+
+    /*
+        Look up the distance to zero of a position in plies, positive when the side to move wins
+        and negative when it loses, draws are 0. Values of 100 or more (or -100 or less) are
+        cursed wins (or blessed losses), the result is off by one ply at times, as the
+        tables count in moves when that was enough to win or lose under the fifty move rule
+    */
+    pub fn probe_dtz(&self, board: &BoardState) -> Option<i32> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        self.dtz(&mut board.clone())
+    }
+
+    /*
+        Get the DTZ of every legal move from the root, counted from the root position,
+        a move that mates has a DTZ of 1
+    */
+    pub fn probe_root(&self, board: &BoardState) -> Option<Vec<(Move, i32)>> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        let mut board = board.clone();
+        let mut moves = Vec::new();
+        for mov in generate_moves(&board, MoveGenerationMode::AllMoves) {
+            let undo = board.make_move(mov, &self.zobrist_hasher);
+            let dtz = if board.half_move_clock == 0 {
+                self.search(&mut board, false)
+                    .map(|(wdl, _)| dtz_before_zeroing(-wdl))
+            } else {
+                self.dtz(&mut board).map(|dtz| {
+                    let dtz = -dtz;
+                    dtz + dtz.signum()
+                })
+            };
+            let mate = dtz == Some(2)
+                && is_check(&board, board.to_move)
+                && generate_moves(&board, MoveGenerationMode::AllMoves).is_empty();
+            board.unmake_move(mov, &undo);
+            moves.push((mov, if mate { 1 } else { dtz? }));
+        }
+        Some(moves)
+    }
+
+    /*
+        Captures are not always stored in the tables, when a capture wins the table may store any
+        value there that compresses well. So probe every capture (and pawn move for DTZ) and
+        take the best of those and the table
+
+        Returns the result and if the best move is a capture or pawn move
+    */
+    fn search(&self, board: &mut BoardState, check_zeroing_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = generate_moves(board, MoveGenerationMode::AllMoves);
+        let mut best = Wdl::Loss;
+        let mut move_count = 0;
+        for &mov in &moves {
+            let zeroing = mov.is_capture() || (check_zeroing_moves && is_pawn_move(board, mov));
+            if !zeroing {
+                continue;
+            }
+            move_count += 1;
+            let undo = board.make_move(mov, &self.zobrist_hasher);
+            let result = self.search(board, false);
+            board.unmake_move(mov, &undo);
+            let value = -result?.0;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // with only captures to play the table may be wrong, en passant is not stored either
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(board, false, Wdl::Draw)? {
+                TableValue::Value(value) => Wdl::from_value(value)?,
+                TableValue::OtherSideToMove => return None,
+            }
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, board: &mut BoardState) -> Option<i32> {
+        let (wdl, zeroing) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        // the table does not store a useful value when the best move zeroes
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(board, true, wdl)? {
+            TableValue::Value(dtz) => {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum())
+            }
+            TableValue::OtherSideToMove => {
+                // find the move that wins the fastest, or loses the slowest
+                let mut min_dtz = 0xffff;
+                for mov in generate_moves(board, MoveGenerationMode::AllMoves) {
+                    let zeroing = mov.is_capture() || is_pawn_move(board, mov);
+                    let undo = board.make_move(mov, &self.zobrist_hasher);
+                    let dtz = if zeroing {
+                        self.search(board, false)
+                            .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+                    } else {
+                        self.dtz(board).map(|dtz| -dtz)
+                    };
+                    let mate = dtz == Some(1)
+                        && is_check(board, board.to_move)
+                        && generate_moves(board, MoveGenerationMode::AllMoves).is_empty();
+                    board.unmake_move(mov, &undo);
+
+                    let mut dtz = dtz?;
+                    if mate {
+                        min_dtz = 1;
+                    }
+                    // zeroing moves already count the move itself
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                // no legal moves, we are mated
+                Some(if min_dtz == 0xffff { -1 } else { min_dtz })
+            }
+        }
+    }
+
+    // Look up the value of a position in its table
+    fn probe_table(&self, board: &BoardState, dtz: bool, wdl: Wdl) -> Option<TableValue> {
+        if piece_count(board) == 2 {
+            // only the kings are left
+            return Some(TableValue::Value(0));
+        }
+        let name = material_name(board);
+        let info = &self.tables[*self.by_material.get(&name)?];
+        let table = info.load(dtz)?;
+
+        // tables with the same pieces on both sides only store white to move
+        let black_to_move = board.to_move == Black;
+        let flip = (info.key == info.key2 && black_to_move) || name != info.key;
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut on_board: Vec<(usize, u8)> = Vec::with_capacity(TB_PIECES);
+        for color in [White, Black] {
+            for kind in PIECE_ORDER {
+                for square in bitboard::squares(board.pieces[color.index()][kind.index()]) {
+                    on_board.push((tb_square(square), tb_piece(color, kind)));
+                }
+            }
+        }
+        on_board.sort_unstable();
+
+        let (file, idx) = encode_position(info, table, &on_board, flip, stm)?;
+        if dtz {
+            let flags = table.pairs(stm, file).flags;
+            let symmetric = info.key == info.key2 && !info.has_pawns;
+            if (flags & FLAG_STM) as usize != stm && !symmetric {
+                return Some(TableValue::OtherSideToMove);
+            }
+        }
+
+        let value = table.decompress_pairs(table.pairs(stm, file), idx)?;
+        if !dtz {
+            return Some(TableValue::Value(value - 2));
+        }
+        Some(TableValue::Value(map_dtz(table, file, value, wdl)?))
+    }
+}
+
+/*
+    Turn a position into an index into its table, returns the file of the leading pawn along with it
+
+    Positions are flipped so the stronger side is white, then mirrored so the leading piece
+    is in the a1-d1-d4 triangle (or the leading pawn on the a to d files), the pieces are
+    then encoded group by group as combinations of the squares left over
+
+    The pieces are given as (square, piece) pairs sorted by square
+*/
+fn encode_position(
+    info: &TableInfo,
+    table: &TableData,
+    on_board: &[(usize, u8)],
+    flip: bool,
+    stm: usize,
+) -> Option<(usize, u64)> {
+    let encoding = encoding();
+    let flip_color = if flip { 8 } else { 0 };
+    let flip_squares = if flip { 56 } else { 0 };
+    let mut squares = [0; TB_PIECES];
+    let mut pieces = [0; TB_PIECES];
+    let mut size = 0;
+    let mut lead_pawns = 0;
+    let mut file = 0;
+    let mut lead_piece = None;
+    if info.has_pawns {
+        let piece = table.pairs(0, 0).pieces[0] ^ flip_color;
+        lead_piece = Some(piece);
+        for &(square, _) in on_board.iter().filter(|&&(_, p)| p == piece) {
+            squares[size] = square ^ flip_squares;
+            size += 1;
+        }
+        lead_pawns = size;
+        let leading = (0..lead_pawns).max_by_key(|&i| encoding.map_pawns[squares[i]])?;
+        squares.swap(0, leading);
+        file = file_of(squares[0]).min(7 - file_of(squares[0]));
+    }
+
+    for &(square, piece) in on_board {
+        if Some(piece) == lead_piece {
+            continue;
+        }
+        squares[size] = square ^ flip_squares;
+        pieces[size] = piece ^ flip_color;
+        size += 1;
+    }
+    if size < 2 {
+        return None;
+    }
+    let d = table.pairs(stm, file);
+
+    // put the pieces in the order the table encodes them in
+    for i in lead_pawns..size - 1 {
+        if let Some(j) = (i + 1..size).find(|&j| d.pieces[i] == pieces[j]) {
+            pieces.swap(i, j);
+            squares.swap(i, j);
+        }
+    }
+
+    if file_of(squares[0]) > 3 {
+        for square in squares[..size].iter_mut() {
+            *square ^= 7;
+        }
+    }
+
+    let mut idx;
+    if info.has_pawns {
+        idx = encoding.lead_pawn_idx[lead_pawns][squares[0]];
+        squares[1..lead_pawns].sort_by_key(|&square| encoding.map_pawns[square]);
+        for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+            idx += encoding.binomial[i][encoding.map_pawns[square]];
+        }
+    } else {
+        if rank_of(squares[0]) > 3 {
+            for square in squares[..size].iter_mut() {
+                *square ^= 56;
+            }
+        }
+        // mirror along the a1-h8 diagonal if the first leading piece off it is above it
+        for i in 0..d.group_len[0] {
+            let off = off_a1h8(squares[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                for square in squares[i..size].iter_mut() {
+                    *square = ((*square >> 3) | (*square << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        if info.has_unique_pieces {
+            let adjust1 = (squares[1] > squares[0]) as u64;
+            let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+            let (s0, s1, s2) = (squares[0], squares[1] as u64, squares[2] as u64);
+            let rank = |square: usize| rank_of(square) as u64;
+            idx = if off_a1h8(s0) != 0 {
+                (encoding.map_a1d1d4[s0] as u64 * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+            } else if off_a1h8(squares[1]) != 0 {
+                (6 * 63 + rank(s0) * 28 + encoding.map_b1h1h7[squares[1]]) * 62 + s2 - adjust2
+            } else if off_a1h8(squares[2]) != 0 {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + rank(s0) * 7 * 28
+                    + (rank(squares[1]) - adjust1) * 28
+                    + encoding.map_b1h1h7[squares[2]]
+            } else {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + 4 * 7 * 28
+                    + rank(s0) * 7 * 6
+                    + (rank(squares[1]) - adjust1) * 6
+                    + (rank(squares[2]) - adjust2)
+            };
+        } else {
+            idx = encoding.map_kk[encoding.map_a1d1d4[squares[0]]][squares[1]];
+        }
+    }
+
+    // the remaining groups are each a combination of the squares not used by earlier groups
+    idx *= d.group_idx[0];
+    let mut group_start = d.group_len[0];
+    let mut remaining_pawns = info.has_pawns && info.pawn_count[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let group_end = group_start + d.group_len[next];
+        squares[group_start..group_end].sort_unstable();
+        let mut n = 0;
+        for i in 0..d.group_len[next] {
+            let square = squares[group_start + i];
+            let adjust = squares[..group_start]
+                .iter()
+                .filter(|&&s| square > s)
+                .count();
+            let below = adjust + if remaining_pawns { 8 } else { 0 };
+            n += encoding.binomial[i + 1][square.checked_sub(below)?];
+        }
+        remaining_pawns = false;
+        idx += n * d.group_idx[next];
+        group_start = group_end;
+        next += 1;
+    }
+
+    Some((file, idx))
+}
+
+// Turn a value stored in a DTZ table into plies
+fn map_dtz(table: &TableData, file: usize, mut value: i32, wdl: Wdl) -> Option<i32> {
+    let d = table.pairs(0, file);
+    if d.flags & FLAG_MAPPED != 0 {
+        let map_idx = d.map_idx[match wdl {
+            Wdl::Win => 0,
+            Wdl::Loss => 1,
+            Wdl::CursedWin => 2,
+            Wdl::BlessedLoss => 3,
+            Wdl::Draw => return None,
+        }];
+        let entry = map_idx + value as usize;
+        value = if d.flags & FLAG_WIDE != 0 {
+            read_u16(&table.bytes, table.map + 2 * entry)? as i32
+        } else {
+            read_u8(&table.bytes, table.map + entry)? as i32
+        };
+    }
+    let in_moves = match wdl {
+        Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+        Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+        _ => true,
+    };
+    if in_moves {
+        value *= 2;
+    }
+    Some(value + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory only this test uses, removed again once the tablebases are opened
+    fn table_dir(test: &str, files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("walleye_syzygy_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, bytes) in files {
+            fs::write(dir.join(name), bytes).unwrap();
+        }
+        dir
+    }
+
+    /*
+        A KQvK WDL table where every position with white to move is a win and
+        every position with black to move is a loss
+    */
+    fn single_value_kqvk() -> Vec<u8> {
+        let mut bytes = WDL_MAGIC.to_vec();
+        bytes.push(1); // split, no pawns
+        bytes.push(0); // group order
+        bytes.extend([0x66, 0x55, 0xee]); // white king, white queen, black king for both sides
+        bytes.push(0); // word alignment
+        bytes.extend([FLAG_SINGLE_VALUE, (Wdl::Win as i32 + 2) as u8]);
+        bytes.extend([FLAG_SINGLE_VALUE, (Wdl::Loss as i32 + 2) as u8]);
+        bytes
+    }
+
+    // A KPvK WDL table with one value for each side to move and leading pawn file
+    fn single_value_kpvk() -> Vec<u8> {
+        let mut bytes = WDL_MAGIC.to_vec();
+        bytes.push(3); // split, pawns
+        for _ in 0..4 {
+            bytes.push(0); // group order
+            bytes.extend([0x11, 0x66, 0xee]); // white pawn, white king, black king
+        }
+        bytes.push(0); // word alignment
+        for _ in 0..8 {
+            bytes.extend([FLAG_SINGLE_VALUE, 2]);
+        }
+        bytes
+    }
+
+    // The eight ways to mirror or rotate a square, in table square numbers
+    fn symmetries(square: usize) -> [usize; 8] {
+        let (file, rank) = (file_of(square), rank_of(square));
+        [
+            (file, rank),
+            (7 - file, rank),
+            (file, 7 - rank),
+            (7 - file, 7 - rank),
+            (rank, file),
+            (7 - rank, file),
+            (rank, 7 - file),
+            (7 - rank, 7 - file),
+        ]
+        .map(|(file, rank)| rank * 8 + file)
+    }
+
+    // Encode pieces given as (square, piece), in any order
+    fn encode(info: &TableInfo, table: &TableData, pieces: &[(usize, u8)]) -> (usize, u64) {
+        let mut on_board = pieces.to_vec();
+        on_board.sort_unstable();
+        encode_position(info, table, &on_board, false, 0).unwrap()
+    }
+
+    fn probe_wdl(tablebases: &Tablebases, fen: &str) -> Option<Wdl> {
+        tablebases.probe_wdl(&BoardState::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn syzygy_encoding_tables() {
+        let encoding = encoding();
+        // every legal placement of the kings has its own index
+        let mut king_codes: Vec<u64> = Vec::new();
+        for (idx, codes) in encoding.map_kk.iter().enumerate() {
+            let first = (0..=27)
+                .find(|&square| encoding.map_a1d1d4[square] == idx && (idx != 0 || square == 1))
+                .unwrap();
+            for (second, &code) in codes.iter().enumerate() {
+                let touching = file_of(first).abs_diff(file_of(second)) <= 1
+                    && rank_of(first).abs_diff(rank_of(second)) <= 1;
+                if !(touching || (off_a1h8(first) == 0 && off_a1h8(second) > 0)) {
+                    king_codes.push(code);
+                }
+            }
+        }
+        king_codes.sort_unstable();
+        assert_eq!(king_codes, (0..462).collect::<Vec<u64>>());
+
+        assert_eq!(encoding.binomial[2][4], 6);
+        assert_eq!(encoding.binomial[3][62], 37820);
+        // one leading pawn can be on any of the 24 squares from a2 to d7
+        assert_eq!(encoding.lead_pawns_size[1].iter().sum::<u64>(), 24);
+        assert_eq!(encoding.map_pawns[8], 47); // a2
+        assert_eq!(encoding.map_pawns[15], 46); // h2
+    }
+
+    #[test]
+    fn syzygy_encoding_without_pawns() {
+        let info = TableInfo::new("KQvK").unwrap();
+        let table = TableData::parse(single_value_kqvk(), &info, false).unwrap();
+        assert_eq!(table.pairs(0, 0).group_idx[1], 31332);
+
+        // positions that are mirror images of each other share an index, all others do not
+        let mut by_index: HashMap<u64, [usize; 3]> = HashMap::new();
+        let mut by_position: HashMap<[usize; 3], u64> = HashMap::new();
+        for white_king in 0..64 {
+            for queen in (0..64).filter(|&square| square != white_king) {
+                for black_king in (0..64).filter(|&square| square != white_king && square != queen)
+                {
+                    let (_, idx) = encode(
+                        &info,
+                        &table,
+                        &[(white_king, 6), (queen, 5), (black_king, 14)],
+                    );
+                    assert!(idx < 31332);
+                    let (a, b, c) = (
+                        symmetries(white_king),
+                        symmetries(queen),
+                        symmetries(black_king),
+                    );
+                    let canonical = (0..8).map(|i| [a[i], b[i], c[i]]).min().unwrap();
+                    assert_eq!(*by_index.entry(idx).or_insert(canonical), canonical);
+                    assert_eq!(*by_position.entry(canonical).or_insert(idx), idx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn syzygy_encoding_with_pawns() {
+        let info = TableInfo::new("KPvK").unwrap();
+        let table = TableData::parse(single_value_kpvk(), &info, false).unwrap();
+        let size = table.pairs(0, 0).group_idx[3];
+        assert_eq!(size, 6 * 63 * 62);
+
+        // every position, up to mirroring the files, has its own index
+        let mut indexes = HashMap::new();
+        for pawn in 8..56 {
+            for white_king in (0..64).filter(|&square| square != pawn) {
+                for black_king in (0..64).filter(|&square| square != pawn && square != white_king) {
+                    let position = [(pawn, 1), (white_king, 6), (black_king, 14)];
+                    let (file, idx) = encode(&info, &table, &position);
+                    assert!(file < 4 && idx < size);
+                    let mirrored = position.map(|(square, piece)| (square ^ 7, piece));
+                    assert_eq!(encode(&info, &table, &mirrored), (file, idx));
+                    *indexes.entry((file, idx)).or_insert(0) += 1;
+                }
+            }
+        }
+        assert_eq!(indexes.len() as u64, 4 * size);
+        assert!(indexes.values().all(|&count| count == 2));
+    }
+
+    #[test]
+    fn syzygy_table_names() {
+        let info = TableInfo::new("KRPvKR").unwrap();
+        assert_eq!(info.key2, "KRvKRP");
+        assert_eq!(info.piece_count, 5);
+        assert!(info.has_pawns && info.has_unique_pieces);
+        assert_eq!(info.pawn_count, [1, 0]);
+
+        let info = TableInfo::new("KPvKPP").unwrap();
+        assert_eq!(info.pawn_count, [1, 2]);
+        assert!(!TableInfo::new("KRRvK").unwrap().has_unique_pieces);
+
+        for name in ["KvKK", "QKvK", "KQv", "KRQvK", "KXvK", "KQK"] {
+            assert!(TableInfo::new(name).is_none(), "{}", name);
+        }
+
+        let board = BoardState::from_fen("8/8/3k4/8/1r6/8/2PKB3/8 w - - 0 1").unwrap();
+        assert_eq!(material_name(&board), "KBPvKR");
+    }
+
+    #[test]
+    fn syzygy_open_finds_tables() {
+        let first = table_dir(
+            "open_first",
+            &[
+                ("KQvK.rtbw", Vec::new()),
+                ("KQvK.rtbz", Vec::new()),
+                ("readme.txt", Vec::new()),
+                ("KXvK.rtbw", Vec::new()),
+            ],
+        );
+        let second = table_dir("open_second", &[("KRvKN.rtbw", Vec::new())]);
+        let path = env::join_paths([&first, &second]).unwrap();
+        let tablebases = Tablebases::open(path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(first).unwrap();
+        fs::remove_dir_all(second).unwrap();
+
+        assert_eq!(tablebases.len(), 2);
+        assert_eq!(tablebases.max_pieces(), 4);
+        let info = &tablebases.tables[tablebases.by_material["KvKQ"]];
+        assert!(info.wdl_path.is_some() && info.dtz_path.is_some());
+        assert!(Tablebases::open("/walleye/no/such/directory").is_err());
+    }
+
+    #[test]
+    fn syzygy_probe_single_value_table() {
+        let dir = table_dir("single_value", &[("KQvK.rtbw", single_value_kqvk())]);
+        let tablebases = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        // the table is only read on the first probe
+        assert_eq!(
+            probe_wdl(&tablebases, "7k/8/8/8/8/8/8/KQ6 w - - 0 1"),
+            Some(Wdl::Win)
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            probe_wdl(&tablebases, "7k/8/8/8/8/8/8/KQ6 b - - 0 1"),
+            Some(Wdl::Loss)
+        );
+        // the colors are reversed, so the table for black to move is used
+        assert_eq!(
+            probe_wdl(&tablebases, "7K/8/8/8/8/8/8/kq6 w - - 0 1"),
+            Some(Wdl::Loss)
+        );
+        assert_eq!(
+            probe_wdl(&tablebases, "7K/8/8/8/8/8/8/kq6 b - - 0 1"),
+            Some(Wdl::Win)
+        );
+        // the queen is hanging, which the table does not know about
+        assert_eq!(
+            probe_wdl(&tablebases, "8/8/8/8/8/8/2k5/1Q5K b - - 0 1"),
+            Some(Wdl::Draw)
+        );
+        assert_eq!(
+            probe_wdl(&tablebases, "8/8/8/8/8/8/8/K6k w - - 0 1"),
+            Some(Wdl::Draw)
+        );
+
+        // no table, too many pieces or castling rights
+        assert_eq!(probe_wdl(&tablebases, "7k/8/8/8/8/8/8/KR6 w - - 0 1"), None);
+        assert_eq!(
+            probe_wdl(&tablebases, "7k/8/8/8/8/8/8/KRR5 w - - 0 1"),
+            None
+        );
+        assert_eq!(
+            probe_wdl(&tablebases, "7k/8/8/8/8/8/8/4K2Q w K - 0 1"),
+            None
+        );
+    }
+
+    #[test]
+    fn syzygy_root_dtz_ranks() {
+        assert_eq!(dtz_rank(15, 0), MAX_DTZ_RANK);
+        assert_eq!(dtz_rank(15, 90), MAX_DTZ_RANK - 105);
+        assert_eq!(dtz_rank(0, 90), 0);
+        assert_eq!(dtz_rank(-15, 0), -MAX_DTZ_RANK);
+        assert_eq!(dtz_rank(-15, 90), -MAX_DTZ_RANK + 105);
+        assert!(dtz_rank(3, 0) > dtz_rank(101, 0));
+        assert!(dtz_rank(-101, 0) > dtz_rank(-3, 0));
+    }
+
+    // Write the two children of a symbol the way the tables store them
+    fn symbol(left: usize, right: usize) -> [u8; 3] {
+        [
+            left as u8,
+            ((left >> 8) as u8 & 0xf) | ((right as u8 & 0xf) << 4),
+            (right >> 4) as u8,
+        ]
+    }
+
+    #[test]
+    fn syzygy_decompress_pairs() {
+        // symbols 0 to 2 are the values 0 to 2, symbol 3 expands to 0 1 and symbol 4 to 0 1 2
+        // symbols 0 and 1 have the codes 000 and 001, symbols 2 to 4 have the codes 01, 10 and 11
+        let mut bytes = vec![0, 3, 2, 0, 2, 3, 2, 0]; // block size, span, padding, block count
+        bytes.extend([3, 2]); // the longest and shortest code lengths
+        bytes.extend([2, 0, 0, 0]); // the lowest symbol with a code of each length
+        bytes.extend([5, 0]);
+        for (left, right) in [(0, LEAF), (1, LEAF), (2, LEAF), (0, 1), (3, 2)] {
+            bytes.extend(symbol(left, right));
+        }
+        bytes.push(0);
+
+        let mut d = PairsData::default();
+        d.group_len[0] = 1;
+        d.group_idx[1] = 9;
+        let offset = set_sizes(&bytes, &mut d, 0).unwrap();
+        assert_eq!(offset, bytes.len());
+        assert_eq!(d.symlen, vec![0, 0, 0, 1, 2]);
+        assert_eq!(d.sparse_index_size, 3);
+
+        // the values 0 1 2 2 1 are in the first block and 0 1 0 2 in the second
+        d.sparse_index = bytes.len();
+        for (block, offset) in [(0u32, 2u16), (1, 1), (1, 5)] {
+            bytes.extend(block.to_le_bytes());
+            bytes.extend(offset.to_le_bytes());
+        }
+        d.block_lengths = bytes.len();
+        bytes.extend([4, 0, 3, 0]);
+        d.data = bytes.len();
+        bytes.extend([0b1101_0010, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend([0b1000_0010, 0, 0, 0, 0, 0, 0, 0]);
+
+        let table = TableData {
+            bytes,
+            pairs: Vec::new(),
+            sides: 1,
+            map: 0,
+        };
+        let values: Vec<i32> = (0..9)
+            .map(|idx| table.decompress_pairs(&d, idx).unwrap())
+            .collect();
+        assert_eq!(values, vec![0, 1, 2, 2, 1, 0, 1, 0, 2]);
+    }
+}
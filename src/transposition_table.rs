@@ -0,0 +1,347 @@
+use crate::board::Move;
+use crate::engine::MATE_SCORE;
+use crate::zobrist::ZobristKey;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+pub const DEFAULT_HASH_SIZE_MB: usize = 16;
+pub const MIN_HASH_SIZE_MB: usize = 1;
+pub const MAX_HASH_SIZE_MB: usize = 1024;
+
+// any score this close to a mate score is treated as a mate when being stored
+const MATE_THRESHOLD: i32 = MATE_SCORE - 1000;
+
+/*
+    Describes how the stored score relates to the true score of the position
+    https://www.chessprogramming.org/Node_Types
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Bound {
+    Exact, // PV node, the score is exact
+    Lower, // Cut node, the true score is at least the stored score
+    Upper, // All node, the true score is at most the stored score
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TranspositionEntry {
+    pub key: ZobristKey,
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    age: u8,
+}
+
+/*
+    Entries are packed into a single u64 so they can be read and written atomically
+
+    bits  0..20 best move (bit 19 is set when there is one)
+    bits 20..44 score
+    bits 44..52 depth
+    bits 52..54 bound, never 0 so an empty slot can be told apart from an entry
+    bits 54..62 age
+*/
+impl TranspositionEntry {
+    fn pack(self) -> u64 {
+        let best_move = self
+            .best_move
+            .map_or(0, |mov| mov.to_bits() as u64 | 1 << 19);
+        let score = (self.score as u64) & 0xff_ffff;
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        best_move | score << 20 | (self.depth as u64) << 44 | bound << 52 | (self.age as u64) << 54
+    }
+
+    fn unpack(key: ZobristKey, data: u64) -> Option<TranspositionEntry> {
+        let bound = match (data >> 52) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+        let best_move = match data & 1 << 19 {
+            0 => None,
+            _ => Some(Move::from_bits(data as u32 & 0x7ffff)),
+        };
+        Some(TranspositionEntry {
+            key,
+            best_move,
+            // shift the sign bit of the 24 bit score back into place
+            score: ((data >> 20) as i32) << 8 >> 8,
+            depth: (data >> 44) as u8,
+            bound,
+            age: (data >> 54) as u8,
+        })
+    }
+}
+
+/*
+    A slot is stored as two words, the key is xor-ed with the entry so an entry torn
+    by two threads writing at the same time does not match the key of either position
+    https://www.chessprogramming.org/Shared_Hash_Table#Lock-less
+*/
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Option<TranspositionEntry> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        TranspositionEntry::unpack(key, data)
+    }
+
+    fn clear(&self) {
+        self.key.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+/*
+    A fixed size hash table of previously searched positions, indexed by zobrist key
+    https://www.chessprogramming.org/Transposition_Table
+
+    The table can be shared by threads searching the same position, every slot is
+    read and written without locking
+*/
+pub struct TranspositionTable {
+    entries: Vec<Slot>,
+    age: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        TranspositionTable {
+            entries: Self::empty_slots(size_mb),
+            age: AtomicU8::new(0),
+        }
+    }
+
+    fn empty_slots(size_mb: usize) -> Vec<Slot> {
+        let size_mb = size_mb.clamp(MIN_HASH_SIZE_MB, MAX_HASH_SIZE_MB);
+        let count = size_mb * 1024 * 1024 / size_of::<Slot>();
+        (0..count).map(|_| Slot::default()).collect()
+    }
+
+    // Resize the table, this will throw away everything that is currently stored
+    pub fn resize(&mut self, size_mb: usize) {
+        self.entries = Self::empty_slots(size_mb);
+        self.age = AtomicU8::new(0);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter().for_each(Slot::clear);
+        self.age = AtomicU8::new(0);
+    }
+
+    // Called at the start of every search so entries from older searches can be replaced first
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn index(&self, key: ZobristKey) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    /*
+        Look up a position, returns None if the position has not been stored or
+        was overwritten by a different position
+    */
+    pub fn probe(&self, key: ZobristKey) -> Option<TranspositionEntry> {
+        self.entries[self.index(key)]
+            .load()
+            .filter(|entry| entry.key == key)
+    }
+
+    /*
+        Store a position in the table
+
+        An existing entry is only replaced if it is from an older search, is the same
+        position, or was searched to a shallower depth
+    */
+    pub fn store(
+        &self,
+        key: ZobristKey,
+        best_move: Option<Move>,
+        score: i32,
+        depth: u8,
+        bound: Bound,
+        ply_from_root: i32,
+    ) {
+        let slot = &self.entries[self.index(key)];
+        let age = self.age.load(Ordering::Relaxed);
+        let existing = slot.load();
+        if let Some(existing) = existing {
+            if existing.age == age && existing.key != key && existing.depth > depth {
+                return;
+            }
+        }
+
+        // keep the best move we already know about if this search did not find one
+        let best_move = match (best_move, existing) {
+            (None, Some(existing)) if existing.key == key => existing.best_move,
+            _ => best_move,
+        };
+
+        let data = TranspositionEntry {
+            key,
+            best_move,
+            score: score_to_tt(score, ply_from_root),
+            depth,
+            bound,
+            age,
+        }
+        .pack();
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+/*
+    Mate scores are relative to the root of the search, but a position can be
+    reached at many different plies. Store mate scores relative to the position
+    itself and convert them back when they are retrieved.
+*/
+fn score_to_tt(score: i32, ply_from_root: i32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score + ply_from_root
+    } else if score <= -MATE_THRESHOLD {
+        score - ply_from_root
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply_from_root: i32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score - ply_from_root
+    } else if score <= -MATE_THRESHOLD {
+        score + ply_from_root
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::board::PieceKind::Knight;
+    use crate::board::Point;
+
+    const MOVE: Option<Move> = Some(Move::new(
+        Point(8, 6),
+        Point(6, 6),
+        None,
+        Move::DOUBLE_PAWN_PUSH,
+    ));
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        tt.store(12345, MOVE, 50, 4, Bound::Exact, 3);
+        let entry = tt.probe(12345).unwrap();
+        assert_eq!(entry.best_move, MOVE);
+        assert_eq!(entry.score, 50);
+        assert_eq!(entry.depth, 4);
+        assert_eq!(entry.bound, Bound::Exact);
+        assert!(tt.probe(54321).is_none());
+    }
+
+    #[test]
+    fn clear_removes_entries() {
+        let mut tt = TranspositionTable::new(1);
+        tt.store(12345, MOVE, 50, 4, Bound::Exact, 0);
+        tt.clear();
+        assert!(tt.probe(12345).is_none());
+    }
+
+    #[test]
+    fn deeper_entries_are_kept() {
+        let tt = TranspositionTable::new(1);
+        let len = tt.entries.len() as u64;
+        tt.store(1, MOVE, 50, 6, Bound::Exact, 0);
+        // same slot, different position and shallower search
+        tt.store(1 + len, None, 10, 2, Bound::Lower, 0);
+        assert!(tt.probe(1).is_some());
+        assert!(tt.probe(1 + len).is_none());
+
+        // entries from an older search are always replaced
+        tt.new_search();
+        tt.store(1 + len, None, 10, 2, Bound::Lower, 0);
+        assert!(tt.probe(1).is_none());
+        assert!(tt.probe(1 + len).is_some());
+    }
+
+    #[test]
+    fn best_move_kept_when_not_provided() {
+        let tt = TranspositionTable::new(1);
+        tt.store(12345, MOVE, 50, 4, Bound::Exact, 0);
+        tt.store(12345, None, -20, 5, Bound::Upper, 0);
+        let entry = tt.probe(12345).unwrap();
+        assert_eq!(entry.best_move, MOVE);
+        assert_eq!(entry.score, -20);
+    }
+
+    #[test]
+    fn mate_scores_adjusted_by_ply() {
+        let tt = TranspositionTable::new(1);
+        // mate in 5 plies from the root, found 3 plies into the search
+        tt.store(12345, MOVE, MATE_SCORE - 5, 4, Bound::Exact, 3);
+        let entry = tt.probe(12345).unwrap();
+        // from the position itself it is a mate in 2 plies
+        assert_eq!(entry.score, MATE_SCORE - 2);
+        // reached again at ply 1 it is a mate in 3 plies from the root
+        assert_eq!(score_from_tt(entry.score, 1), MATE_SCORE - 3);
+
+        tt.store(12345, MOVE, -MATE_SCORE + 5, 4, Bound::Exact, 3);
+        let entry = tt.probe(12345).unwrap();
+        assert_eq!(score_from_tt(entry.score, 1), -MATE_SCORE + 3);
+    }
+
+    #[test]
+    fn entries_packed_without_loss() {
+        let tt = TranspositionTable::new(1);
+        let promotion = Some(Move::new(
+            Point(3, 2),
+            Point(2, 3),
+            Some(Knight),
+            Move::CAPTURE,
+        ));
+        tt.store(777, promotion, -MATE_SCORE + 7, 99, Bound::Lower, 0);
+        let entry = tt.probe(777).unwrap();
+        assert_eq!(entry.best_move, promotion);
+        assert_eq!(entry.score, -MATE_SCORE + 7);
+        assert_eq!(entry.depth, 99);
+        assert_eq!(entry.bound, Bound::Lower);
+
+        tt.store(778, None, 0, 0, Bound::Upper, 0);
+        let entry = tt.probe(778).unwrap();
+        assert_eq!(entry.best_move, None);
+        assert_eq!(entry.bound, Bound::Upper);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let tt = TranspositionTable::new(1);
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for key in 0..1000 {
+                        tt.store(key * 4 + thread, MOVE, thread as i32, 1, Bound::Exact, 0);
+                    }
+                });
+            }
+        });
+        for key in 0..4000 {
+            let entry = tt.probe(key).unwrap();
+            assert_eq!(entry.score, (key % 4) as i32);
+        }
+    }
+}
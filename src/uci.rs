@@ -1,25 +1,23 @@
-pub use crate::board::*;
-pub use crate::board::{PieceColor::*, PieceKind::*};
-use crate::draw_table::DrawTable;
-pub use crate::engine::*;
-pub use crate::move_generation::*;
-pub use crate::time_control::*;
-pub use crate::utils::*;
-use crate::zobrist::ZobristHasher;
+use crate::time_control::*;
 use log::{error, info};
 use std::io::{self, BufRead};
 use std::process;
-use std::sync::mpsc;
-use std::thread;
-use std::time::{Duration, Instant};
-
-const WHITE_KING_SIDE_CASTLE_STRING: &str = "e1g1";
-const WHITE_QUEEN_SIDE_CASTLE_STRING: &str = "e1c1";
-const BLACK_KING_SIDE_CASTLE_STRING: &str = "e8g8";
-const BLACK_QUEEN_SIDE_CASTLE_STRING: &str = "e8c8";
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use walleye::board::*;
+use walleye::engine::{Engine, SearchResult};
+use walleye::polyglot::{BookSelection, PolyglotBook};
+use walleye::search::{SearchLimits, SearchReport, SearchReporter, SearchSignals};
+use walleye::syzygy::Tablebases;
+use walleye::transposition_table::{DEFAULT_HASH_SIZE_MB, MAX_HASH_SIZE_MB, MIN_HASH_SIZE_MB};
+use walleye::utils::*;
+
+const MAX_MULTI_PV: usize = 256;
+const MAX_THREADS: usize = 256;
 
 pub fn play_game_uci() {
-    let mut board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
     let buffer = read_from_gui();
     if buffer != "uci" {
         error!("Expected uci protocol but got {}", buffer);
@@ -33,33 +31,148 @@ pub fn play_game_uci() {
     ));
     send_to_gui(&format!("id author {}", env!("CARGO_PKG_AUTHORS")));
     send_to_gui("option name DebugLogLevel type combo default None var Info var None");
+    send_to_gui(&format!(
+        "option name Hash type spin default {} min {} max {}",
+        DEFAULT_HASH_SIZE_MB, MIN_HASH_SIZE_MB, MAX_HASH_SIZE_MB
+    ));
+    send_to_gui(&format!(
+        "option name Threads type spin default 1 min 1 max {}",
+        MAX_THREADS
+    ));
+    send_to_gui("option name Ponder type check default false");
+    send_to_gui(&format!(
+        "option name MultiPV type spin default 1 min 1 max {}",
+        MAX_MULTI_PV
+    ));
+    send_to_gui("option name OwnBook type check default false");
+    send_to_gui("option name BookFile type string default <empty>");
+    send_to_gui(
+        "option name BookSelection type combo default WeightedRandom var WeightedRandom var Best",
+    );
+    send_to_gui("option name SyzygyPath type string default <empty>");
     send_to_gui("uciok");
 
-    let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-    let mut draw_table = DrawTable::new();
+    let mut engine = Engine::new();
+    engine.set_reporter(Some(uci_reporter()));
+    let signals = Arc::new(SearchSignals::default());
+    let mut search_thread: Option<JoinHandle<()>> = None;
     loop {
         let buffer = read_from_gui();
-        let start = Instant::now();
         let commands: Vec<&str> = buffer.split(' ').collect();
 
         match commands[0] {
             "isready" => send_to_gui("readyok"),
-            "ucinewgame" => (), // we don't keep any internal state really so no need to reset anything here
+            // not part of the protocol, but handy when debugging to see what the engine sees
+            "d" => {
+                for line in engine.board().simple_board().lines().skip(1) {
+                    send_to_gui(line);
+                }
+                send_to_gui(&format!("Fen: {}", engine.board().to_fen()));
+            }
+            "ucinewgame" => {
+                stop_search(&mut search_thread, &signals);
+                engine.new_game();
+            }
             "position" => {
-                draw_table.clear();
-                board = play_out_position(&commands, &zobrist_hasher, &mut draw_table);
-                info!("{}", board.simple_board());
+                stop_search(&mut search_thread, &signals);
+                let (fen, moves) = parse_position_command(&commands);
+                match engine.set_position(&fen, &moves) {
+                    Ok(()) => info!(
+                        "{}{}",
+                        engine.board().simple_board(),
+                        engine.board().to_fen()
+                    ),
+                    Err(err) => error!("{}: {}", err, buffer),
+                }
+            }
+            // not part of the protocol, count the leaf nodes below each move like other engines do
+            "go" if commands.get(1) == Some(&"perft") => {
+                stop_search(&mut search_thread, &signals);
+                match commands
+                    .get(2)
+                    .and_then(|depth| depth.parse::<usize>().ok())
+                {
+                    Some(depth) if depth > 0 => send_perft_divide(&engine, depth),
+                    _ => error!("Invalid perft depth: {}", buffer),
+                }
             }
             "go" => {
-                board = find_and_play_best_move(&commands, &mut board, start, &mut draw_table);
+                stop_search(&mut search_thread, &signals);
+                search_thread = Some(start_search(&commands, &engine, &signals));
             }
+            // the opponent played the move we were pondering on, keep searching but start our clock
+            "ponderhit" => signals.ponder.store(false, Ordering::Relaxed),
+            "stop" => stop_search(&mut search_thread, &signals),
             "setoption" => {
+                stop_search(&mut search_thread, &signals);
                 if commands.contains(&"DebugLogLevel") && commands.contains(&"Info") {
                     // set up logging
                     let log_name = format!("walleye_{}.log", process::id());
                     if simple_logging::log_to_file(log_name, log::LevelFilter::Info).is_err() {
                         panic!("Something went wrong when trying to set up logs");
                     };
+                } else if commands.contains(&"BookFile") {
+                    match parse_option_string(&commands).as_deref() {
+                        None | Some("<empty>") => engine.set_book(None),
+                        Some(path) => match PolyglotBook::from_file(path) {
+                            Ok(book) => {
+                                info!("Loaded {} book entries from {}", book.len(), path);
+                                engine.set_book(Some(book));
+                            }
+                            Err(err) => {
+                                send_to_gui(&format!("info string {}: {}", err, path));
+                                error!("{}: {}", err, path);
+                                engine.set_book(None);
+                            }
+                        },
+                    }
+                } else if commands.contains(&"SyzygyPath") {
+                    match parse_option_string(&commands).as_deref() {
+                        None | Some("<empty>") => engine.set_tablebases(None),
+                        Some(path) => match Tablebases::open(path) {
+                            Ok(tablebases) => {
+                                send_to_gui(&format!(
+                                    "info string found {} tablebases with up to {} pieces",
+                                    tablebases.len(),
+                                    tablebases.max_pieces()
+                                ));
+                                engine.set_tablebases(Some(tablebases));
+                            }
+                            Err(err) => {
+                                send_to_gui(&format!("info string {}: {}", err, path));
+                                error!("{}: {}", err, path);
+                                engine.set_tablebases(None);
+                            }
+                        },
+                    }
+                } else if commands.contains(&"OwnBook") {
+                    match parse_option_value(&commands) {
+                        Some(own_book) => engine.set_own_book(own_book),
+                        None => error!("Invalid value for OwnBook: {}", buffer),
+                    }
+                } else if commands.contains(&"BookSelection") {
+                    match parse_option_string(&commands).as_deref() {
+                        Some("WeightedRandom") => {
+                            engine.set_book_selection(BookSelection::WeightedRandom)
+                        }
+                        Some("Best") => engine.set_book_selection(BookSelection::Best),
+                        _ => error!("Invalid book selection: {}", buffer),
+                    }
+                } else if commands.contains(&"Hash") {
+                    match parse_option_value(&commands) {
+                        Some(size_mb) => engine.set_hash_size(size_mb),
+                        None => error!("Invalid hash size: {}", buffer),
+                    }
+                } else if commands.contains(&"MultiPV") {
+                    match parse_option_value(&commands) {
+                        Some(lines) => engine.set_multi_pv(usize::clamp(lines, 1, MAX_MULTI_PV)),
+                        None => error!("Invalid number of lines: {}", buffer),
+                    }
+                } else if commands.contains(&"Threads") {
+                    match parse_option_value(&commands) {
+                        Some(threads) => engine.set_threads(usize::clamp(threads, 1, MAX_THREADS)),
+                        None => error!("Invalid number of threads: {}", buffer),
+                    }
                 }
             }
             "quit" => process::exit(1),
@@ -69,35 +182,60 @@ pub fn play_game_uci() {
 }
 
 /*
-    Finds an plays the best move and sends it to UCI
-    Returns the new board state with the best move played
+    Start searching for the best move on a separate thread so we can keep listening to the GUI
+    The search thread sends the best move to the GUI once the search is complete
 */
-fn find_and_play_best_move(
+fn start_search(
     commands: &[&str],
-    board: &mut BoardState,
-    start: Instant,
-    draw_table: &mut DrawTable,
-) -> BoardState {
-    let time_to_move_ms = parse_go_command(commands).calculate_time_slice(board.to_move);
-    let mut best_move = None;
-
-    let (tx, rx) = mpsc::channel();
-    let clone = board.clone();
-    let mut draw_clone = draw_table.clone();
-    thread::spawn(move || get_best_move(&clone, &mut draw_clone, start, time_to_move_ms, &tx));
-    // keep looking until we are out of time
-    // also add a guard to ensure we at least get a move from the search thread
-    while !out_of_time(start, time_to_move_ms) || best_move.is_none() {
-        if let Ok(b) = rx.try_recv() {
-            best_move = Some(b);
-        } else {
+    engine: &Engine,
+    signals: &Arc<SearchSignals>,
+) -> JoinHandle<()> {
+    let limits = parse_search_limits(commands, engine.board().to_move);
+    signals.stop.store(false, Ordering::Relaxed);
+    signals
+        .ponder
+        .store(commands.contains(&"ponder"), Ordering::Relaxed);
+    let engine = engine.clone();
+    let signals = Arc::clone(signals);
+    thread::spawn(move || {
+        let result = engine.search_with_signals(limits, Arc::clone(&signals));
+
+        // the GUI does not expect a best move during an infinite search or while we are
+        // pondering, until it sends stop or the opponent plays the ponder move
+        while (limits.infinite || signals.ponder.load(Ordering::Relaxed))
+            && !signals.stop.load(Ordering::Relaxed)
+        {
             thread::sleep(Duration::from_millis(1));
         }
+
+        match result {
+            Some(result) => send_best_move_to_gui(&result),
+            None => send_to_gui("bestmove 0000"),
+        }
+    })
+}
+
+// Send the node count below each move in the current position, then the total
+fn send_perft_divide(engine: &Engine, depth: usize) {
+    let divide = engine.perft_divide(depth);
+    for (mov, nodes) in &divide {
+        send_to_gui(&format!("{}: {}", mov, nodes));
+    }
+    send_to_gui("");
+    send_to_gui(&format!(
+        "Nodes searched: {}",
+        divide.iter().map(|(_, nodes)| nodes).sum::<u64>()
+    ));
+}
+
+/*
+    Tell the search thread to stop, if there is one, and wait for it to send its best move
+*/
+fn stop_search(search_thread: &mut Option<JoinHandle<()>>, signals: &SearchSignals) {
+    if let Some(handle) = search_thread.take() {
+        signals.stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
     }
-    let board = best_move.unwrap();
-    send_best_move_to_gui(&board);
-    info!("{}", board.simple_board());
-    board
 }
 
 // parse the go command and get relevant info about the current game time
@@ -142,190 +280,99 @@ fn parse_go_command(commands: &[&str]) -> GameTime {
 }
 
 /*
-    From the provided fen string set up the board state
+    Parse the go command into the set of limits for the search
+
+    When no fixed move time is given but there is a clock, the time for
+    this move is taken from the clock
 */
-fn play_out_position(
-    commands: &[&str],
-    zobrist_hasher: &ZobristHasher,
-    draw_table: &mut DrawTable,
-) -> BoardState {
-    let mut board;
-    if commands[1] == "fen" {
-        let mut fen = "".to_string();
-        for c in commands.iter().take(7).skip(2) {
-            fen += &format!("{} ", c);
-        }
-        fen += commands[7];
+fn parse_search_limits(commands: &[&str], color: PieceColor) -> SearchLimits {
+    let mut limits = SearchLimits::default();
 
-        board = match BoardState::from_fen(&fen) {
-            Ok(board) => board,
-            Err(err) => {
-                error!("{}", err);
-                panic!("Got bad fen string, cant continue");
+    let mut i = 0;
+    while i < commands.len() {
+        match commands[i] {
+            "infinite" => limits.infinite = true,
+            "depth" if i + 1 < commands.len() => {
+                limits.depth = commands[i + 1].parse().ok();
+                i += 1;
             }
-        };
-    } else {
-        board = BoardState::from_fen(DEFAULT_FEN_STRING).unwrap();
+            "nodes" if i + 1 < commands.len() => {
+                limits.nodes = commands[i + 1].parse().ok();
+                i += 1;
+            }
+            "movetime" if i + 1 < commands.len() => {
+                limits.movetime = commands[i + 1].parse().ok();
+                i += 1;
+            }
+            "mate" if i + 1 < commands.len() => {
+                limits.mate = commands[i + 1].parse().ok();
+                i += 1;
+            }
+            _ => (),
+        }
+        i += 1;
     }
 
-    let mut moves_start_index = None;
-    for (i, command) in commands.iter().enumerate() {
-        if command == &"moves" {
-            moves_start_index = Some(i);
-            break;
-        }
+    let has_clock = commands.contains(&"wtime") || commands.contains(&"btime");
+    if limits.movetime.is_none() && has_clock && !limits.infinite {
+        limits.movetime = Some(parse_go_command(commands).calculate_time_slice(color));
     }
 
-    draw_table.table.insert(board.zobrist_key, 1);
+    limits
+}
 
-    if let Some(start_index) = moves_start_index {
-        for mov in commands.iter().skip(start_index + 1) {
-            make_move(&mut board, *mov, zobrist_hasher);
-            draw_table.add_board_to_draw_table(&board);
-        }
-    }
+// parse the value out of a "setoption name <id> value <x>" command
+fn parse_option_value<T: std::str::FromStr>(commands: &[&str]) -> Option<T> {
+    let value_index = commands.iter().position(|c| *c == "value")?;
+    commands.get(value_index + 1)?.parse().ok()
+}
 
-    board
+// parse the value out of a "setoption name <id> value <x>" command where the value can contain spaces
+fn parse_option_string(commands: &[&str]) -> Option<String> {
+    let value_index = commands.iter().position(|c| *c == "value")?;
+    let value = commands[value_index + 1..].join(" ");
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
 }
 
 /*
-    Play the opponents move on the board
+    Split the position command into the fen string to start from and the moves played since
 */
-fn make_move(board: &mut BoardState, player_move: &str, zobrist_hasher: &ZobristHasher) {
-    let start_pair: Point = (player_move[0..2]).parse().unwrap();
-    let end_pair: Point = (player_move[2..4]).parse().unwrap();
-    board.unset_pawn_double_move(zobrist_hasher);
-
-    if let Square::Full(piece) = board.board[start_pair.0][start_pair.1] {
-        // update king location
-        if piece.kind == King {
-            if piece.color == White {
-                board.white_king_location = end_pair;
-                board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-                board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-            } else {
-                board.black_king_location = end_pair;
-                board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-                board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-            }
-        } else if piece.kind == Pawn {
-            if (start_pair.0 as i8 - end_pair.0 as i8).abs() == 2 {
-                // pawn made a double move, record space behind pawn for en passant
-                let target = match piece.color {
-                    White => Point(start_pair.0 - 1, start_pair.1),
-                    Black => Point(start_pair.0 + 1, start_pair.1),
-                };
-                board.zobrist_key ^= zobrist_hasher.get_val_for_en_passant(target.1);
-                board.pawn_double_move = Some(target);
-            }
-            // check for en passant captures
-            // if a pawn moves diagonally and no capture is made, it must be an en passant capture
-            if start_pair.1 != end_pair.1 && board.board[end_pair.0][end_pair.1] == Square::Empty {
-                board.board[start_pair.0][end_pair.1] = Square::Empty;
-                board.zobrist_key ^= zobrist_hasher.get_val_for_piece(
-                    Piece::pawn(board.to_move.opposite()),
-                    Point(start_pair.0, end_pair.1),
-                );
-            }
-        }
+fn parse_position_command<'a>(commands: &[&'a str]) -> (String, Vec<&'a str>) {
+    let moves_index = commands.iter().position(|c| *c == "moves");
+    let fen = if commands.get(1) == Some(&"fen") {
+        commands[2..moves_index.unwrap_or(commands.len())].join(" ")
     } else {
-        panic!("UCI Error: Trying to move a piece that does not exist");
-    }
-
-    //deal with castling privileges related to the movement/capture of rooks
-    if player_move.contains("a8") {
-        board.take_away_castling_rights(CastlingType::BlackQueenSide, zobrist_hasher);
-    }
-    if player_move.contains("h8") {
-        board.take_away_castling_rights(CastlingType::BlackKingSide, zobrist_hasher);
-    }
-    if player_move.contains("a1") {
-        board.take_away_castling_rights(CastlingType::WhiteQueenSide, zobrist_hasher);
-    }
-    if player_move.contains("h1") {
-        board.take_away_castling_rights(CastlingType::WhiteKingSide, zobrist_hasher);
-    }
-
-    //move piece
-    board.move_piece(start_pair, end_pair, zobrist_hasher);
-
-    //deal with any pawn promotions
-    if player_move.len() == 5 {
-        let kind = match player_move.chars().nth(4).unwrap() {
-            'q' => Queen,
-            'n' => Knight,
-            'b' => Bishop,
-            'r' => Rook,
-            _ => {
-                error!("Could not recognize piece value, default to queen");
-                Queen
-            }
-        };
-        let promotion_piece = Piece {
-            color: board.to_move,
-            kind,
-        };
-        board.zobrist_key ^= zobrist_hasher.get_val_for_piece(Piece::pawn(board.to_move), end_pair)
-            ^ zobrist_hasher.get_val_for_piece(promotion_piece, end_pair);
-        board.board[end_pair.0][end_pair.1] = promotion_piece.into();
-    }
+        DEFAULT_FEN_STRING.to_string()
+    };
 
-    // deal with castling, here we also make sure the right king is on the target square to
-    // distinguish between castling and normal moves
-    if player_move == WHITE_KING_SIDE_CASTLE_STRING
-        && board.board[end_pair.0][end_pair.1] == Piece::king(White)
-    {
-        board.move_piece(
-            Point(BOARD_END - 1, BOARD_END - 1),
-            Point(BOARD_END - 1, BOARD_END - 3),
-            zobrist_hasher,
-        );
-    } else if player_move == WHITE_QUEEN_SIDE_CASTLE_STRING
-        && board.board[end_pair.0][end_pair.1] == Piece::king(White)
-    {
-        board.move_piece(
-            Point(BOARD_END - 1, BOARD_START),
-            Point(BOARD_END - 1, BOARD_START + 3),
-            zobrist_hasher,
-        );
-    } else if player_move == BLACK_KING_SIDE_CASTLE_STRING
-        && board.board[end_pair.0][end_pair.1] == Piece::king(Black)
-    {
-        board.move_piece(
-            Point(BOARD_START, BOARD_END - 1),
-            Point(BOARD_START, BOARD_END - 3),
-            zobrist_hasher,
-        );
-    } else if player_move == BLACK_QUEEN_SIDE_CASTLE_STRING
-        && board.board[end_pair.0][end_pair.1] == Piece::king(Black)
-    {
-        board.move_piece(
-            Point(BOARD_START, BOARD_START),
-            Point(BOARD_START, BOARD_START + 3),
-            zobrist_hasher,
-        );
-    }
+    let moves = match moves_index {
+        Some(index) => commands[index + 1..].to_vec(),
+        None => Vec::new(),
+    };
 
-    board.swap_color(zobrist_hasher);
+    (fen, moves)
 }
 
-fn send_best_move_to_gui(board: &BoardState) {
-    let best_move = board.last_move.unwrap();
-    if let Some(pawn_promotion) = board.pawn_promotion {
-        send_to_gui(&format!(
-            "bestmove {}{}{}",
-            best_move.0,
-            best_move.1,
-            pawn_promotion.kind.alg()
-        ));
-    } else {
-        send_to_gui(&format!("bestmove {}{}", best_move.0, best_move.1));
-    }
+// Send every report from the search to the GUI as an info line
+pub fn uci_reporter() -> SearchReporter {
+    Arc::new(|report: &SearchReport| send_to_gui(&report.to_string()))
 }
 
-pub fn send_to_gui(message: &str) {
-    println!("{}", message);
-    info!("ENGINE >> {}", message);
+fn send_best_move_to_gui(result: &SearchResult) {
+    if result.from_book {
+        send_to_gui(&format!("info string book move {}", result.best_move));
+    }
+    match result.ponder_move {
+        Some(ponder_move) => send_to_gui(&format!(
+            "bestmove {} ponder {}",
+            result.best_move, ponder_move
+        )),
+        None => send_to_gui(&format!("bestmove {}", result.best_move)),
+    }
 }
 
 pub fn read_from_gui() -> String {
@@ -340,6 +387,16 @@ pub fn read_from_gui() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use walleye::board::PieceColor::*;
+
+    fn play_out_position(buffer: &str) -> BoardState {
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let (fen, moves) = parse_position_command(&commands);
+        let mut engine = Engine::new();
+        engine.set_position(&fen, &moves).unwrap();
+        engine.board().clone()
+    }
+
     #[test]
     fn can_parse_go_command_no_inc() {
         let buffer = "go wtime 12345 btime 300000 movestogo 40";
@@ -377,53 +434,92 @@ mod tests {
     }
 
     #[test]
-    fn en_passant_capture_parsed_correctly_black() {
-        let mut board = BoardState::from_fen("8/1k6/8/8/7p/8/1K4P1/8 w - - 0 1").unwrap();
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        make_move(&mut board, "g2g4", &zobrist_hasher);
-        make_move(&mut board, "h4g3", &zobrist_hasher);
-        assert_eq!(board.board[7][8], Square::from(Piece::pawn(Black)));
-
-        let mut pawn_count = 0;
-        for i in BOARD_START..BOARD_END {
-            for j in BOARD_START..BOARD_END {
-                if let Square::Full(Piece { kind, .. }) = board.board[i][j] {
-                    if kind == Pawn {
-                        pawn_count += 1;
-                    }
-                }
-            }
-        }
-        assert_eq!(pawn_count, 1);
+    fn can_parse_search_limits() {
+        let buffer = "go depth 6 nodes 100000 mate 3";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let res = parse_search_limits(&commands, White);
+        assert_eq!(res.depth, Some(6));
+        assert_eq!(res.nodes, Some(100000));
+        assert_eq!(res.mate, Some(3));
+        assert_eq!(res.movetime, None);
+        assert!(!res.infinite);
     }
 
     #[test]
-    fn en_passant_capture_parsed_correctly_white() {
-        let mut board = BoardState::from_fen("8/1k4p1/8/5P2/8/8/1K6/8 b - - 0 1").unwrap();
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        make_move(&mut board, "g7g5", &zobrist_hasher);
-        make_move(&mut board, "f5g6", &zobrist_hasher);
-        assert_eq!(board.board[4][8], Square::from(Piece::pawn(White)));
-
-        let mut pawn_count = 0;
-        for i in BOARD_START..BOARD_END {
-            for j in BOARD_START..BOARD_END {
-                if let Square::Full(Piece { kind, .. }) = board.board[i][j] {
-                    if kind == Pawn {
-                        pawn_count += 1;
-                    }
-                }
-            }
-        }
-        assert_eq!(pawn_count, 1);
+    fn can_parse_search_limits_movetime() {
+        let buffer = "go wtime 300000 btime 300000 movetime 2500";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let res = parse_search_limits(&commands, White);
+        assert_eq!(res.movetime, Some(2500));
+        assert_eq!(res.depth, None);
+    }
+
+    #[test]
+    fn can_parse_search_limits_clock() {
+        let buffer = "go wtime 30100 btime 60100 movestogo 10";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(parse_search_limits(&commands, White).movetime, Some(2400));
+        assert_eq!(parse_search_limits(&commands, Black).movetime, Some(4800));
+    }
+
+    #[test]
+    fn can_parse_search_limits_infinite() {
+        let buffer = "go infinite";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let res = parse_search_limits(&commands, White);
+        assert!(res.infinite);
+        assert_eq!(res.movetime, None);
+    }
+
+    #[test]
+    fn can_parse_position_command() {
+        let buffer = "position startpos moves e2e4 e7e5";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let (fen, moves) = parse_position_command(&commands);
+        assert_eq!(fen, DEFAULT_FEN_STRING);
+        assert_eq!(moves, vec!["e2e4", "e7e5"]);
+
+        let buffer = "position fen 8/8/k7/p7/P7/K7/8/8 w - - 0 1";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        let (fen, moves) = parse_position_command(&commands);
+        assert_eq!(fen, "8/8/k7/p7/P7/K7/8/8 w - - 0 1");
+        assert!(moves.is_empty());
+    }
+
+    #[test]
+    fn can_parse_option_value() {
+        let buffer = "setoption name Hash value 128";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(parse_option_value::<usize>(&commands), Some(128));
+
+        let buffer = "setoption name Hash value lots";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(parse_option_value::<usize>(&commands), None);
+
+        let buffer = "setoption name Hash";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(parse_option_value::<usize>(&commands), None);
+    }
+
+    #[test]
+    fn can_parse_option_string() {
+        let buffer = "setoption name BookFile value /home/books/my book.bin";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(
+            parse_option_string(&commands),
+            Some("/home/books/my book.bin".to_string())
+        );
+
+        let buffer = "setoption name BookFile value";
+        let commands: Vec<&str> = buffer.split(' ').collect();
+        assert_eq!(parse_option_string(&commands), None);
     }
 
     #[test]
     fn full_game_played_white_wins() {
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        let mut draw_table: DrawTable = DrawTable::new();
-        let commands: Vec<&str> = "position startpos moves g1f3 g8f6 d2d4 d7d5 e2e3 e7e6 f1d3 b8c6 b1c3 f8e7 e1g1 e8g8 a2a3 h7h6 b2b4 a7a6 c1b2 e7d6 a1c1 b7b5 h2h3 c8b7 f1e1 f8e8 g2g3 d8d7 e3e4 e6e5 c3d5 f6d5 e4d5 c6d4 f3d4 e5d4 d1h5 d6e7 b2d4 d7d5 h5d5 b7d5 c2c4 b5c4 d3c4 d5c4 c1c4 e7d6 e1e8 a8e8 c4c6 e8e1 g1g2 e1d1 d4e3 d1a1 c6a6 d6b4 a3a4 h6h5 a6a8 g8h7 a8a7 h7g6 a7c7 a1a4 c7c4 g6f6 e3d2 b4d2 c4a4 d2c3 g2f3 f6e6 f3e4 f7f5 e4e3 e6f7 e3f4 c3e1 f2f3 g7g6 a4a7 f7e6 f4g5 e1g3 a7a6 e6e5 g5g6 e5d4 a6e6 h5h4 g6f5 d4c3 e6e8 g3f2 e8d8 c3c4 f5g4 f2e1 f3f4 c4b3 f4f5 e1c3 g4g5 c3a5 d8e8 a5d2 g5h4 d2c3 h4g5 b3c4 f5f6 c3b2 f6f7 b2a3 g5g6 c4d5 h3h4 d5c4 h4h5 a3d6 h5h6 d6f8 e8f8 c4d5 f8d8 d5e5 f7f8q e5e4 f8f2 e4e5 f2f5".split(' ').collect();
-        let board = play_out_position(&commands, &zobrist_hasher, &mut draw_table);
+        let board = play_out_position(
+            "position startpos moves g1f3 g8f6 d2d4 d7d5 e2e3 e7e6 f1d3 b8c6 b1c3 f8e7 e1g1 e8g8 a2a3 h7h6 b2b4 a7a6 c1b2 e7d6 a1c1 b7b5 h2h3 c8b7 f1e1 f8e8 g2g3 d8d7 e3e4 e6e5 c3d5 f6d5 e4d5 c6d4 f3d4 e5d4 d1h5 d6e7 b2d4 d7d5 h5d5 b7d5 c2c4 b5c4 d3c4 d5c4 c1c4 e7d6 e1e8 a8e8 c4c6 e8e1 g1g2 e1d1 d4e3 d1a1 c6a6 d6b4 a3a4 h6h5 a6a8 g8h7 a8a7 h7g6 a7c7 a1a4 c7c4 g6f6 e3d2 b4d2 c4a4 d2c3 g2f3 f6e6 f3e4 f7f5 e4e3 e6f7 e3f4 c3e1 f2f3 g7g6 a4a7 f7e6 f4g5 e1g3 a7a6 e6e5 g5g6 e5d4 a6e6 h5h4 g6f5 d4c3 e6e8 g3f2 e8d8 c3c4 f5g4 f2e1 f3f4 c4b3 f4f5 e1c3 g4g5 c3a5 d8e8 a5d2 g5h4 d2c3 h4g5 b3c4 f5f6 c3b2 f6f7 b2a3 g5g6 c4d5 h3h4 d5c4 h4h5 a3d6 h5h6 d6f8 e8f8 c4d5 f8d8 d5e5 f7f8q e5e4 f8f2 e4e5 f2f5",
+        );
         let end_board = BoardState::from_fen("3R4/8/6KP/4kQ2/8/8/8/8 b - - 4 66").unwrap();
 
         for i in BOARD_START..BOARD_END {
@@ -451,10 +547,9 @@ mod tests {
 
     #[test]
     fn zobrist_hash_full_game_played_white_wins() {
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        let mut draw_table: DrawTable = DrawTable::new();
-        let commands: Vec<&str> = "position startpos moves g1f3 g8f6 d2d4 d7d5 e2e3 e7e6 f1d3 b8c6 b1c3 f8e7 e1g1 e8g8 a2a3 h7h6 b2b4 a7a6 c1b2 e7d6 a1c1 b7b5 h2h3 c8b7 f1e1 f8e8 g2g3 d8d7 e3e4 e6e5 c3d5 f6d5 e4d5 c6d4 f3d4 e5d4 d1h5 d6e7 b2d4 d7d5 h5d5 b7d5 c2c4 b5c4 d3c4 d5c4 c1c4 e7d6 e1e8 a8e8 c4c6 e8e1 g1g2 e1d1 d4e3 d1a1 c6a6 d6b4 a3a4 h6h5 a6a8 g8h7 a8a7 h7g6 a7c7 a1a4 c7c4 g6f6 e3d2 b4d2 c4a4 d2c3 g2f3 f6e6 f3e4 f7f5 e4e3 e6f7 e3f4 c3e1 f2f3 g7g6 a4a7 f7e6 f4g5 e1g3 a7a6 e6e5 g5g6 e5d4 a6e6 h5h4 g6f5 d4c3 e6e8 g3f2 e8d8 c3c4 f5g4 f2e1 f3f4 c4b3 f4f5 e1c3 g4g5 c3a5 d8e8 a5d2 g5h4 d2c3 h4g5 b3c4 f5f6 c3b2 f6f7 b2a3 g5g6 c4d5 h3h4 d5c4 h4h5 a3d6 h5h6 d6f8 e8f8 c4d5 f8d8 d5e5 f7f8q e5e4 f8f2 e4e5 f2f5".split(' ').collect();
-        let board = play_out_position(&commands, &zobrist_hasher, &mut draw_table);
+        let board = play_out_position(
+            "position startpos moves g1f3 g8f6 d2d4 d7d5 e2e3 e7e6 f1d3 b8c6 b1c3 f8e7 e1g1 e8g8 a2a3 h7h6 b2b4 a7a6 c1b2 e7d6 a1c1 b7b5 h2h3 c8b7 f1e1 f8e8 g2g3 d8d7 e3e4 e6e5 c3d5 f6d5 e4d5 c6d4 f3d4 e5d4 d1h5 d6e7 b2d4 d7d5 h5d5 b7d5 c2c4 b5c4 d3c4 d5c4 c1c4 e7d6 e1e8 a8e8 c4c6 e8e1 g1g2 e1d1 d4e3 d1a1 c6a6 d6b4 a3a4 h6h5 a6a8 g8h7 a8a7 h7g6 a7c7 a1a4 c7c4 g6f6 e3d2 b4d2 c4a4 d2c3 g2f3 f6e6 f3e4 f7f5 e4e3 e6f7 e3f4 c3e1 f2f3 g7g6 a4a7 f7e6 f4g5 e1g3 a7a6 e6e5 g5g6 e5d4 a6e6 h5h4 g6f5 d4c3 e6e8 g3f2 e8d8 c3c4 f5g4 f2e1 f3f4 c4b3 f4f5 e1c3 g4g5 c3a5 d8e8 a5d2 g5h4 d2c3 h4g5 b3c4 f5f6 c3b2 f6f7 b2a3 g5g6 c4d5 h3h4 d5c4 h4h5 a3d6 h5h6 d6f8 e8f8 c4d5 f8d8 d5e5 f7f8q e5e4 f8f2 e4e5 f2f5",
+        );
         let end_board = BoardState::from_fen("3R4/8/6KP/4kQ2/8/8/8/8 b - - 4 66").unwrap();
 
         assert_eq!(board.zobrist_key, end_board.zobrist_key);
@@ -462,25 +557,14 @@ mod tests {
 
     #[test]
     fn zobrist_hash_full_game_played_white_wins_2() {
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        let mut draw_table: DrawTable = DrawTable::new();
         // this game contains en-passant, castling and pawn promotion
-        let commands: Vec<&str> = "position startpos moves e2e4 d7d5 e4e5 f7f5 e5f6 b8c6 f6g7 c8e6 g7h8q d8d6 d2d3 e8c8 d1h5 c6a5 h8g8 e6d7 g8f8 a5c6 h5g4 h7h6 g4a4 c6d4 a4a7 h6h5 a7a8".split(' ').collect();
-        let board = play_out_position(&commands, &zobrist_hasher, &mut draw_table);
+        let board = play_out_position(
+            "position startpos moves e2e4 d7d5 e4e5 f7f5 e5f6 b8c6 f6g7 c8e6 g7h8q d8d6 d2d3 e8c8 d1h5 c6a5 h8g8 e6d7 g8f8 a5c6 h5g4 h7h6 g4a4 c6d4 a4a7 h6h5 a7a8",
+        );
         let end_board =
             BoardState::from_fen("Q1kr1Q2/1ppbp3/3q4/3p3p/3n4/3P4/PPP2PPP/RNB1KBNR b KQ - 1 13")
                 .unwrap();
 
         assert_eq!(board.zobrist_key, end_board.zobrist_key);
     }
-
-    #[test]
-    fn game_is_draw_three_fold_repetition() {
-        let zobrist_hasher = ZobristHasher::create_zobrist_hasher();
-        let mut draw_table: DrawTable = DrawTable::new();
-        // this game contains en-passant, castling and pawn promotion
-        let commands: Vec<&str> = "position fen 8/8/k7/p7/P7/K7/8/8 w - - 0 1 moves a3b3 a6b6 b3c4 b6c6 c4d4 c6d6 d4c4 d6c6 c4d4 c6d6 d4c4 d6c6".split(' ').collect();
-        let board = play_out_position(&commands, &zobrist_hasher, &mut draw_table);
-        assert_eq!(*draw_table.table.get(&board.zobrist_key).unwrap(), 3);
-    }
 }
@@ -1,3 +1,4 @@
+use log::info;
 use std::time::Instant;
 
 /*
@@ -35,6 +36,14 @@ pub fn out_of_time(start: Instant, time_to_move_ms: u128) -> bool {
     Instant::now().duration_since(start).as_millis() >= time_to_move_ms
 }
 
+/*
+    Send a message to the GUI over stdout, all messages are logged as well
+*/
+pub fn send_to_gui(message: &str) {
+    println!("{}", message);
+    info!("ENGINE >> {}", message);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;